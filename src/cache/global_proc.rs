@@ -7,7 +7,7 @@ use mlua::{prelude::LuaResult, Function, Lua, OwnedFunction, Variadic};
 
 use crate::{traits::AsPrintedExternalResult, wrappers::wrapped_global_call};
 
-use crate::value::Value;
+use crate::value::{convert_proc_result, Value};
 
 /// Wrapper struct to differentiate cached object functions from cached global functions, as internally, both are `HashMap<String, OwnedFunction>`
 #[derive(Default)]
@@ -39,8 +39,10 @@ pub fn global_proccall_function(lua: &Lua, proc: String) -> LuaResult<OwnedFunct
                 Ok(func)
             } else {
                 let proc_name = proc.clone();
-                lua.create_function(move |_, args: Variadic<Value>| {
-                    wrapped_global_call(proc_name.clone(), args.to_vec()).into_printed_external()
+                lua.create_function(move |lua, args: Variadic<Value>| {
+                    wrapped_global_call(proc_name.clone(), args.to_vec())
+                        .into_printed_external()
+                        .and_then(|result| convert_proc_result(lua, result))
                 })
                 .map(Function::into_owned)
                 .inspect(|func| {
@@ -7,13 +7,18 @@ mod value;
 pub(crate) mod wrappers;
 
 pub use state::{
-    awaken, call_function, clear_execution_limit, clear_ref_userdata, clear_state_execution_limit,
-    collect_garbage, get_globals, get_traceback, is_isolated, kill_sleeping_thread, kill_state,
-    kill_yielded_thread, list_threads, load, new_state, resume, set_execution_limit_millis,
-    set_execution_limit_secs, set_state_execution_limit_millis, set_state_execution_limit_secs,
-    set_usr,
+    awaken, awaken_all, awaken_group, awaken_thread, call_function, clear_execution_limit,
+    clear_max_call_depth, clear_ref_userdata, clear_state_execution_limit, clear_yield_timeout,
+    collect_garbage, get_globals, get_traceback, is_isolated, kill_sleeping_thread,
+    kill_sleeping_thread_by_id, kill_state, kill_thread_group, kill_yielded_thread, list_threads,
+    load, new_state, prioritize_sleeping_thread, reap_expired_threads, resume, resume_with_error,
+    set_execution_limit_millis, set_execution_limit_secs, set_list_append, set_max_call_depth,
+    set_state_execution_limit_millis, set_state_execution_limit_secs, set_usr,
+    set_vector_conversion, set_yield_timeout_millis, set_yield_timeout_secs,
 };
 
+pub use value::{set_max_conversion_depth, set_max_conversion_elements};
+
 pub use wrappers::{
     set_global_call_wrapper, set_new_wrapper, set_object_call_wrapper, set_print_wrapper,
     set_var_get_wrapper, set_var_set_wrapper,
@@ -0,0 +1,74 @@
+use std::cell::RefCell;
+
+use dreamluau_proc_macro::map_statics;
+use meowtonin::ByondValue;
+use mlua::{
+    prelude::{LuaResult, LuaValue},
+    Lua, RegistryKey,
+};
+
+use crate::value::{convert_to_table, ToTableOptions, Value};
+
+struct ContextEntry {
+    context: Option<ByondValue>,
+    /// The context converted to a read-only table, once it has been read during the call.
+    table: Option<RegistryKey>,
+}
+
+thread_local! {
+    static CONTEXT_STACK: RefCell<Vec<ContextEntry>> = const { RefCell::new(vec![]) };
+}
+
+#[map_statics(mut CONTEXT_STACK)]
+pub fn push_context(context: Option<ByondValue>) {
+    context_stack.push(ContextEntry {
+        context,
+        table: None,
+    })
+}
+
+#[map_statics(mut CONTEXT_STACK)]
+pub fn pop_context() {
+    context_stack.pop();
+}
+
+/// Unlike `usr`, the context is not inherited from outer calls,
+/// so each nested call only sees the context it was invoked with.
+#[map_statics(CONTEXT_STACK)]
+pub fn peek_context() -> Option<ByondValue> {
+    context_stack.last().and_then(|entry| entry.context.clone())
+}
+
+#[map_statics(CONTEXT_STACK)]
+fn peek_context_table(lua: &Lua) -> LuaResult<Option<LuaValue<'_>>> {
+    context_stack
+        .last()
+        .and_then(|entry| entry.table.as_ref())
+        .map(|key| lua.registry_value(key))
+        .transpose()
+}
+
+#[map_statics(mut CONTEXT_STACK)]
+fn cache_context_table(key: RegistryKey) {
+    if let Some(entry) = context_stack.last_mut() {
+        entry.table = Some(key)
+    }
+}
+
+/// Returns the context of the innermost call as a read-only table.
+///
+/// The context is converted the first time it is read during a call, and that table is reused for the rest of the call.
+pub fn context_table(lua: &Lua) -> LuaResult<LuaValue<'_>> {
+    if let Some(table) = peek_context_table(lua)? {
+        return Ok(table);
+    }
+    let Some(context) = peek_context() else {
+        return Ok(LuaValue::Nil);
+    };
+    let converted = convert_to_table(lua, (Value(context), ToTableOptions::default()))?;
+    if let LuaValue::Table(ref table) = converted {
+        table.set_readonly(true);
+    }
+    cache_context_table(lua.create_registry_value(converted.clone())?);
+    Ok(converted)
+}
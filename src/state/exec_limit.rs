@@ -10,10 +10,16 @@ use mlua::{
     Lua, VmState,
 };
 
+/// The default limit on how many threads can be run within each other,
+/// low enough that mutual recursion between luau and DM cannot overflow Dream Daemon's native stack.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 32;
+
 thread_local! {
     static EXECUTION_LIMIT: RefCell<Option<Duration>> = const { RefCell::new(Some(Duration::from_millis(100))) };
     static EXECUTION_START: RefCell<Option<Instant>> = const { RefCell::new(None) };
-    pub static CALL_DEPTH: RefCell<usize> = const { RefCell::new(0) };
+    /// The names of the threads currently being run, from outermost to innermost.
+    static CALL_CHAIN: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+    static MAX_CALL_DEPTH: RefCell<Option<usize>> = const { RefCell::new(Some(DEFAULT_MAX_CALL_DEPTH)) };
     static PRIVILEGED_EXECUTION: RefCell<bool> = const { RefCell::new(false) };
 }
 
@@ -44,18 +50,55 @@ pub fn get_execution_limit() -> Option<Duration> {
     *execution_limit
 }
 
-#[map_statics(mut EXECUTION_START, mut CALL_DEPTH)]
-pub fn increment_call_depth() {
-    if *call_depth == 0 {
+/// Sets the maximum number of threads that can be run within each other,
+/// such as when luau calls a DM proc that calls back into luau.
+#[map_statics(mut MAX_CALL_DEPTH)]
+#[byond_fn]
+pub fn set_max_call_depth(new_limit: u32) {
+    max_call_depth.replace(new_limit as usize);
+}
+
+#[map_statics(mut MAX_CALL_DEPTH)]
+#[byond_fn]
+pub fn clear_max_call_depth() {
+    max_call_depth.take();
+}
+
+#[map_statics(MAX_CALL_DEPTH)]
+pub fn get_max_call_depth() -> Option<usize> {
+    *max_call_depth
+}
+
+#[map_statics(CALL_CHAIN)]
+pub fn get_call_depth() -> usize {
+    call_chain.len()
+}
+
+/// Returns an error describing the chain of threads that led to this point
+/// if running the named thread would exceed the maximum call depth.
+#[map_statics(CALL_CHAIN, MAX_CALL_DEPTH)]
+pub fn check_call_depth(name: &str) -> LuaResult<()> {
+    match *max_call_depth {
+        Some(max) if call_chain.len() >= max => Err(LuaError::external(format!(
+            "maximum call depth of {max} exceeded: {} -> {name}",
+            call_chain.join(" -> ")
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[map_statics(mut EXECUTION_START, mut CALL_CHAIN)]
+pub fn increment_call_depth(name: String) {
+    if call_chain.is_empty() {
         execution_start.replace(Instant::now());
     }
-    *call_depth += 1
+    call_chain.push(name)
 }
 
-#[map_statics(mut EXECUTION_START, mut CALL_DEPTH)]
+#[map_statics(mut EXECUTION_START, mut CALL_CHAIN)]
 pub fn decrement_call_depth() {
-    *call_depth -= 1;
-    if *call_depth == 0 {
+    call_chain.pop();
+    if call_chain.is_empty() {
         execution_start.take();
     }
 }
@@ -70,8 +113,9 @@ pub fn set_privileged_execution(privileged: bool) {
     *privileged_execution = privileged;
 }
 
+/// Returns an error if the state's execution limit, or the global one if the state has none, has been exceeded.
 #[map_statics(EXECUTION_START, EXECUTION_LIMIT, PRIVILEGED_EXECUTION)]
-pub fn limiting_interrupt(lua: &Lua) -> LuaResult<VmState> {
+pub fn check_execution_limit(lua: &Lua) -> LuaResult<()> {
     match (
         lua.named_registry_value::<Option<f32>>("exec_limit")?
             .map(Duration::from_secs_f32)
@@ -79,14 +123,18 @@ pub fn limiting_interrupt(lua: &Lua) -> LuaResult<VmState> {
         execution_start,
         privileged_execution,
     ) {
-        (_, _, true) => Ok(VmState::Continue),
-        (Some(limit), Some(start), _) => (start.elapsed() <= limit)
-            .then_some(VmState::Continue)
-            .ok_or_else(|| {
+        (_, _, true) => Ok(()),
+        (Some(limit), Some(start), _) => {
+            (start.elapsed() <= limit).then_some(()).ok_or_else(|| {
                 LuaError::external(
                     "execution limit reached - call sleep or coroutine.yield before this point",
                 )
-            }),
-        (_, _, _) => Ok(VmState::Continue),
+            })
+        }
+        (_, _, _) => Ok(()),
     }
 }
+
+pub fn limiting_interrupt(lua: &Lua) -> LuaResult<VmState> {
+    check_execution_limit(lua).map(|()| VmState::Continue)
+}
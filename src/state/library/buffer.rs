@@ -0,0 +1,20 @@
+use mlua::{
+    prelude::{LuaResult, LuaValue},
+    Function, IntoLua, Lua,
+};
+
+use crate::value::create_buffer_from_dm;
+
+use super::LuaModule;
+
+/// Additions to the `buffer` library for creating buffers from DM values.
+pub struct BufferModule;
+
+impl LuaModule for BufferModule {
+    fn create_items<'lua>(&self, lua: &'lua Lua) -> LuaResult<Vec<(&str, LuaValue<'lua>)>> {
+        Ok(vec![(
+            "fromdm",
+            Function::wrap(create_buffer_from_dm).into_lua(lua)?,
+        )])
+    }
+}
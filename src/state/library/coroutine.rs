@@ -0,0 +1,72 @@
+use mlua::{
+    prelude::{LuaResult, LuaValue},
+    Function, Lua,
+};
+
+use crate::state::sleep::{sleep, take_yield_flag, yield_to_dm};
+
+use super::LuaModule;
+
+/// Replacements for `coroutine.resume` and `coroutine.wrap`.
+///
+/// When a coroutine resumed by one of these functions is suspended by `sleep` or `yield_to_dm`,
+/// the coroutine that resumed it is suspended the same way, until the outermost thread is suspended back to DM.
+/// Once that thread is awakened or resumed, the values passed into it are passed back down into the nested coroutine.
+const COROUTINE_WRAPPERS: &str = r#"
+local sleep, yield_to_dm, take_yield_flag = ...
+local create, resume = coroutine.create, coroutine.resume
+local pack, unpack = table.pack, table.unpack
+
+local function propagate(co, results)
+	while results[1] do
+		local kind = take_yield_flag()
+		if kind == "sleep" then
+			results = pack(resume(co, sleep()))
+		elseif kind == "yield" then
+			results = pack(resume(co, yield_to_dm(unpack(results, 2, results.n))))
+		else
+			break
+		end
+	end
+	return results
+end
+
+local function propagating_resume(co, ...)
+	local results = propagate(co, pack(resume(co, ...)))
+	return unpack(results, 1, results.n)
+end
+
+local function propagating_wrap(f)
+	local co = create(f)
+	return function(...)
+		local results = propagate(co, pack(resume(co, ...)))
+		if not results[1] then
+			error(results[2], 0)
+		end
+		return unpack(results, 2, results.n)
+	end
+end
+
+return propagating_resume, propagating_wrap
+"#;
+
+/// Unit struct that replaces the coroutine library's `resume` and `wrap` functions
+/// with versions that propagate sleeps and yields to DM out of nested coroutines.
+pub struct CoroutineModule;
+
+impl LuaModule for CoroutineModule {
+    fn create_items<'lua>(&self, lua: &'lua Lua) -> LuaResult<Vec<(&str, LuaValue<'lua>)>> {
+        let (resume, wrap) = lua
+            .load(COROUTINE_WRAPPERS)
+            .set_name("coroutine")
+            .call::<_, (Function, Function)>((
+                unsafe { lua.create_c_function(sleep) }?,
+                unsafe { lua.create_c_function(yield_to_dm) }?,
+                lua.create_function(|_, ()| Ok(take_yield_flag().map(|kind| kind.name())))?,
+            ))?;
+        Ok(vec![
+            ("resume", LuaValue::Function(resume)),
+            ("wrap", LuaValue::Function(wrap)),
+        ])
+    }
+}
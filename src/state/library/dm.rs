@@ -1,16 +1,21 @@
 use std::collections::HashMap;
 
+use meowtonin::{misc::locate_xyz, ByondXYZ};
 use mlua::{
-    prelude::{Lua, LuaResult, LuaValue},
-    Function, IntoLua, Variadic,
+    prelude::{Lua, LuaError, LuaResult, LuaValue},
+    Function, IntoLua, Table, Variadic, Vector,
 };
 
 use crate::{
     helpers::{GLOBALS, WORLD},
-    state::usr::peek_usr,
+    state::{context::context_table, usr::peek_usr},
     traits::AsPrintedExternalResult,
-    value::{ByondObject, Value},
-    wrappers::{wrapped_new, wrapped_read_var},
+    types::ATOM_TYPES,
+    value::{list_operator, text_to_typepath, types_of, ByondObject, Value},
+    wrappers::{
+        wrapped_new, wrapped_read_initial, wrapped_read_initial_vars, wrapped_read_var,
+        wrapped_read_vars,
+    },
 };
 
 use super::{global_procs::GlobalProcsModule, LuaModule, MetafieldItems};
@@ -24,6 +29,27 @@ impl DmModule {
         wrapped_new(type_, args.to_vec()).into_printed_external()
     }
 
+    /// Logically equivalent to `text2path(path)`.
+    fn path(_: &Lua, path: String) -> LuaResult<Value> {
+        text_to_typepath(&path).map(Value).into_printed_external()
+    }
+
+    fn typesof(_: &Lua, Value(path): Value) -> LuaResult<Value> {
+        types_of(&path).map(Value).into_printed_external()
+    }
+
+    /// Logically equivalent to `typesof(path) - path`.
+    fn subtypesof(_: &Lua, Value(path): Value) -> LuaResult<Value> {
+        let path = if path.is_string() {
+            text_to_typepath(&path.get_string().into_printed_external()?).into_printed_external()?
+        } else {
+            path
+        };
+        types_of(&path)
+            .into_printed_external()
+            .and_then(|types| list_operator("-", &types, &path))
+    }
+
     fn is_valid_ref(_: &Lua, value: LuaValue) -> LuaResult<bool> {
         Ok(match value {
             LuaValue::UserData(ud) => ud
@@ -39,9 +65,86 @@ impl DmModule {
         peek_usr().map(Value).into_lua(lua)
     }
 
+    fn context(lua: &'_ Lua) -> LuaResult<LuaValue<'_>> {
+        context_table(lua)
+    }
+
     fn get_var(_: &Lua, (Value(ref src), var): (Value, String)) -> LuaResult<Value> {
         wrapped_read_var(src, var).into_printed_external()
     }
+
+    fn initial(_: &Lua, (Value(ref src), var): (Value, String)) -> LuaResult<Value> {
+        wrapped_read_initial(src, var).into_printed_external()
+    }
+
+    fn initial_vars(lua: &Lua, Value(ref src): Value) -> LuaResult<Table<'_>> {
+        let (vars, entries) = wrapped_read_initial_vars(src).into_printed_external()?;
+        // The values are converted while the list they were read from is still alive.
+        let _vars = ByondObject::new(vars);
+        let table = lua.create_table_with_capacity(0, entries.len())?;
+        entries
+            .into_iter()
+            .try_for_each(|[name, value]| table.raw_set(Value(name), Value(value)))?;
+        Ok(table)
+    }
+
+    fn vars(lua: &Lua, Value(ref datum): Value) -> LuaResult<Function<'_>> {
+        let entries = lua.create_table()?;
+        wrapped_read_vars(datum)
+            .into_printed_external()?
+            .into_iter()
+            .try_for_each(|(name, value)| {
+                entries.raw_push(
+                    lua.create_sequence_from([Value(name).into_lua(lua)?, value.into_lua(lua)?])?,
+                )
+            })?;
+        // The vars are converted up front, so that their values are kept alive until the iteration is finished.
+        let entries = lua.create_registry_value(entries)?;
+        let mut index = 0;
+        lua.create_function_mut(move |lua, ()| {
+            index += 1;
+            match lua
+                .registry_value::<Table>(&entries)?
+                .raw_get::<_, Option<Table>>(index)?
+            {
+                Some(entry) => Ok((
+                    entry.raw_get::<_, LuaValue>(1)?,
+                    entry.raw_get::<_, LuaValue>(2)?,
+                )),
+                None => Ok((LuaValue::Nil, LuaValue::Nil)),
+            }
+        })
+    }
+
+    fn vec(_: &Lua, Value(ref atom): Value) -> LuaResult<Option<Vector>> {
+        if !ATOM_TYPES.contains(&atom.get_type().0) {
+            return Err(LuaError::external("expected an atom"));
+        }
+        let coordinate = |var: &str| {
+            wrapped_read_var(atom, var.to_string())
+                .and_then(|Value(coordinate)| coordinate.get_number())
+                .into_printed_external()
+        };
+        let (x, y, z) = (coordinate("x")?, coordinate("y")?, coordinate("z")?);
+        // Atoms that are not on the map have all of their coordinates set to 0.
+        Ok((z != 0.0).then(|| Vector::new(x, y, z)))
+    }
+
+    fn locate(_: &Lua, coordinates: Vector) -> LuaResult<Value> {
+        let coordinate = |c: f32| {
+            (c.fract() == 0.0 && c >= i16::MIN as f32 && c <= i16::MAX as f32)
+                .then_some(c as i16)
+                .ok_or_else(|| LuaError::external(format!("invalid coordinate {c}")))
+        };
+        let (x, y, z) = (
+            coordinate(coordinates.x())?,
+            coordinate(coordinates.y())?,
+            coordinate(coordinates.z())?,
+        );
+        locate_xyz(ByondXYZ::new(x, y, z))
+            .map(Value)
+            .into_printed_external()
+    }
 }
 
 impl LuaModule for DmModule {
@@ -55,6 +158,20 @@ impl LuaModule for DmModule {
             ),
             ("get_var", Function::wrap(Self::get_var).into_lua(lua)?),
             ("new", Function::wrap(Self::new).into_lua(lua)?),
+            ("vars", Function::wrap(Self::vars).into_lua(lua)?),
+            ("initial", Function::wrap(Self::initial).into_lua(lua)?),
+            (
+                "initial_vars",
+                Function::wrap(Self::initial_vars).into_lua(lua)?,
+            ),
+            ("vec", Function::wrap(Self::vec).into_lua(lua)?),
+            ("locate", Function::wrap(Self::locate).into_lua(lua)?),
+            ("path", Function::wrap(Self::path).into_lua(lua)?),
+            ("typesof", Function::wrap(Self::typesof).into_lua(lua)?),
+            (
+                "subtypesof",
+                Function::wrap(Self::subtypesof).into_lua(lua)?,
+            ),
             (
                 "is_valid_ref",
                 Function::wrap(Self::is_valid_ref).into_lua(lua)?,
@@ -70,6 +187,9 @@ impl LuaModule for DmModule {
     }
 
     fn create_metafield_items(&self) -> Option<MetafieldItems> {
-        Some(vec![("usr".into(), Box::new(Self::usr))])
+        Some(vec![
+            ("usr".into(), Box::new(Self::usr)),
+            ("context".into(), Box::new(Self::context)),
+        ])
     }
 }
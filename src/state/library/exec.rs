@@ -6,7 +6,7 @@ use mlua::{
 };
 
 use crate::state::{
-    exec_limit::{get_execution_limit, get_execution_time},
+    exec_limit::{get_call_depth, get_execution_limit, get_execution_time, get_max_call_depth},
     threads::next_yield_index,
 };
 
@@ -38,6 +38,14 @@ impl ExecModule {
             .map(|opt| opt.map(LuaValue::Integer))
             .and_then(|opt| opt.into_lua(lua))
     }
+
+    fn call_depth(lua: &'_ Lua) -> LuaResult<LuaValue<'_>> {
+        get_call_depth().into_lua(lua)
+    }
+
+    fn max_call_depth(lua: &'_ Lua) -> LuaResult<LuaValue<'_>> {
+        get_max_call_depth().into_lua(lua)
+    }
 }
 
 impl LuaModule for ExecModule {
@@ -46,6 +54,8 @@ impl LuaModule for ExecModule {
             ("next_yield_index".into(), Box::new(next_yield_index)),
             ("limit".into(), Box::new(Self::exec_limit)),
             ("time".into(), Box::new(Self::exec_time)),
+            ("call_depth".into(), Box::new(Self::call_depth)),
+            ("max_call_depth".into(), Box::new(Self::max_call_depth)),
         ])
     }
 }
@@ -5,9 +5,16 @@ use mlua::{
     Function, IntoLua, Lua, Variadic,
 };
 
-use crate::{state::sleep::sleep, value::Value, wrappers::print};
+use crate::{
+    state::sleep::{sleep, yield_to_dm},
+    value::Value,
+    wrappers::print,
+};
 
-use super::{dm::DmModule, exec::ExecModule, list::ListModule, pointer::PointerModule, LuaModule};
+use super::{
+    dm::DmModule, exec::ExecModule, json::JsonModule, list::ListModule, pointer::PointerModule,
+    LuaModule,
+};
 
 /// Struct that appends the global table with global modules and functions that wouldn't fit any better within a specific module.
 ///
@@ -32,11 +39,16 @@ impl LuaModule for GlobalModule {
                 "sleep",
                 unsafe { lua.create_c_function(sleep) }.map(LuaValue::Function)?,
             ),
+            (
+                "yield_to_dm",
+                unsafe { lua.create_c_function(yield_to_dm) }.map(LuaValue::Function)?,
+            ),
             (
                 "print",
                 Function::wrap(move |_, args: Variadic<Value>| print(id1, args)).into_lua(lua)?,
             ),
             ("_exec", (&ExecModule as &dyn LuaModule).into_lua(lua)?),
+            ("json", (&JsonModule as &dyn LuaModule).into_lua(lua)?),
             ("_state_id", LuaValue::Integer(id)),
         ];
         if !isolate {
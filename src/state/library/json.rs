@@ -0,0 +1,276 @@
+use std::{collections::HashSet, ffi::CString, fmt::Write, ptr};
+
+use meowtonin::{ByondValue, FromByond};
+use mlua::{
+    prelude::{LuaError, LuaResult, LuaValue},
+    FromLua, Function, IntoLua, LightUserData, Lua, Table,
+};
+use serde_json::Value as JsonValue;
+
+use crate::{
+    traits::AsPrintedExternalResult,
+    value::{ordered_pairs, ByondObject},
+};
+
+use super::LuaModule;
+
+/// The maximum nesting depth of tables that can be encoded, matching the nesting limit `serde_json` enforces when decoding.
+const MAX_ENCODE_DEPTH: usize = 128;
+
+/// The value of `json.null`, which JSON `null` is decoded as so that arrays containing it do not end up with holes.
+const JSON_NULL: LuaValue<'static> = LuaValue::LightUserData(LightUserData(ptr::null_mut()));
+
+/// How DM lists or datum-like objects are encoded.
+#[derive(Clone, Copy, Default)]
+enum ReferenceMode {
+    /// Encoded as their reference string, in the same format as `ref()` in DM.
+    Ref,
+    /// Encoded as the result of converting them to text.
+    String,
+    /// Encoding them raises an error.
+    #[default]
+    Error,
+}
+
+impl ReferenceMode {
+    fn from_option(options: &Table, name: &str) -> LuaResult<Self> {
+        match options.get::<_, Option<String>>(name)?.as_deref() {
+            None | Some("error") => Ok(Self::Error),
+            Some("ref") => Ok(Self::Ref),
+            Some("string") => Ok(Self::String),
+            Some(other) => Err(LuaError::external(format!(
+                "invalid {name} mode \"{other}\" (expected \"ref\", \"string\", or \"error\")"
+            ))),
+        }
+    }
+}
+
+#[derive(Default)]
+struct EncodeOptions {
+    lists: ReferenceMode,
+    objects: ReferenceMode,
+}
+
+impl<'lua> FromLua<'lua> for EncodeOptions {
+    fn from_lua(value: LuaValue<'lua>, _: &'lua Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Table(options) => Ok(Self {
+                lists: ReferenceMode::from_option(&options, "lists")?,
+                objects: ReferenceMode::from_option(&options, "objects")?,
+            }),
+            other => Err(LuaError::FromLuaConversionError {
+                from: other.type_name(),
+                to: "encoding options",
+                message: Some(String::from("expected a table")),
+            }),
+        }
+    }
+}
+
+/// Unit struct for encoding and decoding JSON without going through DM.
+pub struct JsonModule;
+
+impl JsonModule {
+    fn encode(lua: &Lua, (value, options): (LuaValue, EncodeOptions)) -> LuaResult<String> {
+        let mut output = String::new();
+        encode_value(lua, &value, &options, &mut HashSet::new(), &mut output)?;
+        Ok(output)
+    }
+
+    fn decode(lua: &Lua, text: String) -> LuaResult<LuaValue<'_>> {
+        serde_json::from_str::<JsonValue>(&text)
+            .map_err(LuaError::external)
+            .and_then(|json| decode_value(lua, json))
+    }
+}
+
+fn write_string(string: &str, output: &mut String) -> LuaResult<()> {
+    serde_json::to_string(string)
+        .map(|escaped| output.push_str(&escaped))
+        .map_err(LuaError::external)
+}
+
+fn encode_reference(
+    value: &ByondValue,
+    mode: ReferenceMode,
+    kind: &str,
+    output: &mut String,
+) -> LuaResult<()> {
+    match mode {
+        ReferenceMode::Ref => match value.ref_id() {
+            Some(id) => write_string(&format!("[0x{:x}{id:06x}]", value.get_type().0), output),
+            None => Err(LuaError::external(format!("cannot encode {kind} without a reference"))),
+        },
+        ReferenceMode::String => CString::from_byond(value.clone())
+            .into_printed_external()
+            .and_then(|string| write_string(&string.to_string_lossy(), output)),
+        ReferenceMode::Error => Err(LuaError::external(format!(
+            "cannot encode {kind} (set the \"{kind}s\" option to \"ref\" or \"string\" to allow this)"
+        ))),
+    }
+}
+
+/// Luau has no separate integer type, so integral numbers are written without a fractional part.
+fn write_number(number: f64, output: &mut String) -> LuaResult<()> {
+    if number.fract() == 0.0 && number.abs() < 2f64.powi(53) {
+        write!(output, "{}", number as i64)
+    } else {
+        match serde_json::Number::from_f64(number) {
+            Some(number) => write!(output, "{number}"),
+            None => {
+                return Err(LuaError::external(format!(
+                    "cannot encode {number} as a number"
+                )))
+            }
+        }
+    }
+    .map_err(LuaError::external)
+}
+
+/// Returns whether every key of the table is part of its array portion.
+fn is_array(pairs: &[(LuaValue, LuaValue)], length: usize) -> bool {
+    pairs.len() == length
+        && pairs.iter().all(|(key, _)| match key {
+            LuaValue::Integer(i) => *i >= 1 && *i as usize <= length,
+            LuaValue::Number(n) => n.fract() == 0.0 && *n >= 1.0 && *n <= length as f64,
+            _ => false,
+        })
+}
+
+fn encode_value(
+    lua: &Lua,
+    value: &LuaValue,
+    options: &EncodeOptions,
+    encoding: &mut HashSet<usize>,
+    output: &mut String,
+) -> LuaResult<()> {
+    match value {
+        LuaValue::Nil => output.push_str("null"),
+        null if *null == JSON_NULL => output.push_str("null"),
+        LuaValue::Boolean(b) => output.push_str(if *b { "true" } else { "false" }),
+        LuaValue::Integer(i) => write!(output, "{i}").map_err(LuaError::external)?,
+        LuaValue::Number(n) => write_number(*n, output)?,
+        LuaValue::String(s) => write_string(s.to_str()?, output)?,
+        LuaValue::Table(t) => encode_table(lua, t, options, encoding, output)?,
+        LuaValue::UserData(ud) if ud.is::<ByondObject>() => {
+            let object = ud.borrow::<ByondObject>()?;
+            if object.0.is_list() {
+                encode_reference(&object.0, options.lists, "list", output)?
+            } else {
+                encode_reference(&object.0, options.objects, "object", output)?
+            }
+        }
+        other => {
+            return Err(LuaError::external(format!(
+                "cannot encode a value of type {}",
+                other.type_name()
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn encode_table(
+    lua: &Lua,
+    table: &Table,
+    options: &EncodeOptions,
+    encoding: &mut HashSet<usize>,
+    output: &mut String,
+) -> LuaResult<()> {
+    // Tables that appear more than once are encoded each time,
+    // but a table that contains itself can never finish encoding.
+    let ptr = table.to_pointer() as usize;
+    // `encoding` holds every table currently being encoded, so its size is the current nesting depth.
+    if encoding.len() >= MAX_ENCODE_DEPTH {
+        return Err(LuaError::external(format!(
+            "cannot encode tables nested more than {MAX_ENCODE_DEPTH} deep"
+        )));
+    }
+    if !encoding.insert(ptr) {
+        return Err(LuaError::external(
+            "cannot encode a table that contains itself",
+        ));
+    }
+    let pairs = match ordered_pairs(lua, table)? {
+        Some(pairs) => pairs,
+        None => table
+            .clone()
+            .pairs::<LuaValue, LuaValue>()
+            .collect::<LuaResult<Vec<_>>>()?,
+    };
+    let length = table.raw_len();
+    // Empty tables are encoded as arrays, matching how DM encodes empty lists.
+    if is_array(&pairs, length) {
+        output.push('[');
+        for index in 1..=length {
+            if index > 1 {
+                output.push(',');
+            }
+            encode_value(lua, &table.raw_get(index)?, options, encoding, output)?;
+        }
+        output.push(']');
+    } else {
+        output.push('{');
+        for (i, (key, value)) in pairs.iter().enumerate() {
+            if i > 0 {
+                output.push(',');
+            }
+            match key {
+                LuaValue::String(s) => write_string(s.to_str()?, output)?,
+                LuaValue::Integer(_) | LuaValue::Number(_) => {
+                    let mut number = String::new();
+                    encode_value(lua, key, options, encoding, &mut number)?;
+                    write_string(&number, output)?
+                }
+                other => {
+                    return Err(LuaError::external(format!(
+                        "cannot encode a table with keys of type {}",
+                        other.type_name()
+                    )))
+                }
+            }
+            output.push(':');
+            encode_value(lua, value, options, encoding, output)?;
+        }
+        output.push('}');
+    }
+    encoding.remove(&ptr);
+    Ok(())
+}
+
+fn decode_value(lua: &Lua, json: JsonValue) -> LuaResult<LuaValue<'_>> {
+    match json {
+        JsonValue::Null => Ok(JSON_NULL),
+        JsonValue::Bool(b) => Ok(LuaValue::Boolean(b)),
+        JsonValue::Number(n) => Ok(LuaValue::Number(n.as_f64().unwrap_or(f64::NAN))),
+        JsonValue::String(s) => s.into_lua(lua),
+        JsonValue::Array(elements) => {
+            let table = lua.create_table_with_capacity(elements.len(), 0)?;
+            elements
+                .into_iter()
+                .zip(1..)
+                .try_for_each(|(element, index)| {
+                    table.raw_set(index, decode_value(lua, element)?)
+                })?;
+            Ok(LuaValue::Table(table))
+        }
+        JsonValue::Object(entries) => {
+            let table = lua.create_table_with_capacity(0, entries.len())?;
+            entries
+                .into_iter()
+                .try_for_each(|(key, value)| table.raw_set(key, decode_value(lua, value)?))?;
+            Ok(LuaValue::Table(table))
+        }
+    }
+}
+
+impl LuaModule for JsonModule {
+    fn create_items<'lua>(&self, lua: &'lua Lua) -> LuaResult<Vec<(&str, LuaValue<'lua>)>> {
+        Ok(vec![
+            ("encode", Function::wrap(Self::encode).into_lua(lua)?),
+            ("decode", Function::wrap(Self::decode).into_lua(lua)?),
+            ("null", JSON_NULL),
+        ])
+    }
+}
@@ -2,14 +2,20 @@ use std::collections::HashMap;
 
 use crate::{
     traits::AsPrintedExternalResult,
-    value::{convert_from_table, convert_to_table, Value},
+    types::{can_index_by_anything, type_name_for_obj, TYPEPATH_TYPES},
+    value::{
+        as_list_element, convert_from_pairs, convert_from_table, convert_to_table,
+        create_ordered_table, list_operator, ordered_pairs, resolve_list_index, ByondObject,
+        ListView, Value,
+    },
+    wrappers::{wrapped_read_list_index, wrapped_read_list_range},
 };
 
 use super::LuaModule;
-use meowtonin::{ByondError, ByondValue, ToByond};
+use meowtonin::{call_global, ByondError, ByondResult, ByondValue, ToByond};
 use mlua::{
-    prelude::{LuaResult, LuaValue},
-    Function, IntoLua, Lua, Variadic,
+    prelude::{LuaError, LuaResult, LuaValue},
+    FromLua, Function, IntoLua, Lua, Table, Variadic,
 };
 
 /// Unit struct for DM list procs and conversion to/from lua tables
@@ -141,20 +147,487 @@ impl ListModule {
         .into_printed_external()
     }
 
-    fn filter(_: &Lua, (Value(this), type_): (Value, String)) -> LuaResult<Value> {
-        this.iter()
-            .map(|iter| {
-                iter.filter(|(key, _)| {
-                    key.typepath()
-                        .map(|typepath| typepath.starts_with(&type_))
-                        .unwrap_or(false)
-                })
-                .collect::<Vec<(ByondValue, ByondValue)>>()
+    /// Logically equivalent to `list.Add(value)` with two arguments, or `list.Insert(index, value)` with three.
+    fn table_insert(_: &Lua, (Value(this), args): (Value, Variadic<Value>)) -> LuaResult<()> {
+        if !this.is_list() {
+            return Err(ByondError::NotAList).into_printed_external();
+        }
+        match args.as_slice() {
+            [Value(value)] => as_list_element(value)
+                .and_then(|element| this.call::<_, _, _, ByondValue>("Add", [element])),
+            [Value(index), Value(value)] => as_list_element(value).and_then(|element| {
+                this.call::<_, _, _, ByondValue>("Insert", [index.clone(), element])
+            }),
+            _ => {
+                return Err(LuaError::external(
+                    "wrong number of arguments to 'table_insert'",
+                ))
+            }
+        }
+        .map(|_| ())
+        .into_printed_external()
+    }
+
+    /// Cuts the element at `index` out of the list, defaulting to the last element, and returns it.
+    /// Negative indices count back from the end of the list, and other indices are bounds checked the same way as `table.remove`.
+    fn table_remove(_: &Lua, (Value(this), index): (Value, Option<i32>)) -> LuaResult<Value> {
+        if !this.is_list() {
+            return Err(ByondError::NotAList).into_printed_external();
+        }
+        let length = this.length().into_printed_external()? as i32;
+        let index = match index {
+            Some(index) => resolve_list_index(&this, ByondValue::new_num(index as f32))?
+                .get_number()
+                .into_printed_external()? as i32,
+            None => length,
+        };
+        if index == length + 1 || (index == 0 && length == 0) {
+            return Ok(Value(ByondValue::NULL));
+        }
+        if !(1..=length).contains(&index) {
+            return Err(LuaError::external("position out of bounds"));
+        }
+        let removed = wrapped_read_list_index(&this, index).into_printed_external()?;
+        this.call::<_, _, _, ByondValue>("Cut", [index, index + 1])
+            .map(|_| removed)
+            .into_printed_external()
+    }
+
+    /// Logically equivalent to `list.Join(separator, start, end + 1)`, as `table.concat` includes the element at `end`.
+    fn table_concat(
+        _: &Lua,
+        (Value(this), separator, start, end): (Value, Option<String>, Option<usize>, Option<usize>),
+    ) -> LuaResult<Value> {
+        if this.is_list() {
+            this.call::<_, _, _, Value>(
+                "Join",
+                [
+                    ByondValue::new_string(separator.unwrap_or_default()),
+                    ByondValue::new_num(start.unwrap_or(1) as f32),
+                    ByondValue::new_num(end.map_or(0, |end| end + 1) as f32),
+                ],
+            )
+        } else {
+            Err(ByondError::NotAList)
+        }
+        .into_printed_external()
+    }
+
+    /// Logically equivalent to `new /list(size)`.
+    fn new_list(_: &Lua, size: Option<u32>) -> LuaResult<Value> {
+        ByondValue::new_list_len(size.unwrap_or(0))
+            .map(Value)
+            .into_printed_external()
+    }
+
+    /// Creates a list of the arguments. Unlike `list.add`, list arguments are added as single elements.
+    fn of(_: &Lua, items: Variadic<Value>) -> LuaResult<Value> {
+        items
+            .iter()
+            .map(|Value(item)| item.clone())
+            .collect::<Vec<_>>()
+            .to_byond()
+            .map(Value)
+            .into_printed_external()
+    }
+
+    /// Creates a list whose keys are the keys of the table, associated with the table's values.
+    fn assoc(lua: &Lua, table: Table) -> LuaResult<Value> {
+        let pairs = match ordered_pairs(lua, &table)? {
+            Some(pairs) => pairs,
+            None => table
+                .pairs::<LuaValue, LuaValue>()
+                .collect::<LuaResult<Vec<_>>>()?,
+        };
+        let entries = pairs
+            .into_iter()
+            .map(|(key, value)| {
+                let Value(key) = Value::from_lua(key, lua)?;
+                if key.is_number() {
+                    return Err(LuaError::external("numbers cannot be used as assoc keys"));
+                }
+                Ok((key, Value::from_lua(value, lua)?.0))
             })
-            .and_then(|vec| vec.to_byond())
+            .collect::<LuaResult<Vec<_>>>()?;
+        entries.to_byond().map(Value).into_printed_external()
+    }
+
+    /// Logically equivalent to `left | right`.
+    fn union(_: &Lua, (Value(left), Value(right)): (Value, Value)) -> LuaResult<Value> {
+        list_operator("|", &left, &right)
+    }
+
+    /// Logically equivalent to `left & right`.
+    fn intersect(_: &Lua, (Value(left), Value(right)): (Value, Value)) -> LuaResult<Value> {
+        list_operator("&", &left, &right)
+    }
+
+    /// Logically equivalent to `left - right`.
+    fn difference(_: &Lua, (Value(left), Value(right)): (Value, Value)) -> LuaResult<Value> {
+        list_operator("-", &left, &right)
+    }
+
+    /// Logically equivalent to `left ^ right`.
+    fn symmetric_difference(
+        _: &Lua,
+        (Value(left), Value(right)): (Value, Value),
+    ) -> LuaResult<Value> {
+        list_operator("^", &left, &right)
+    }
+
+    /// Sorts the list in place. The entries are read once, sorted in rust, then written back,
+    /// so the list keeps its identity and any assoc values stay with their keys.
+    fn sort(lua: &Lua, (Value(this), comparator): (Value, Option<Function>)) -> LuaResult<()> {
+        if !this.is_list() {
+            return Err(ByondError::NotAList).into_printed_external();
+        }
+        if !can_index_by_anything(&this) {
+            return Err(LuaError::external(format!(
+                "cannot sort a {}",
+                type_name_for_obj(&this)
+            )));
+        }
+        // The copy keeps every value referenced while the list is being cut and refilled.
+        let (_copy, entries) = read_entries(&this, true)?;
+        let sorted = match comparator {
+            Some(comparator) => {
+                let keyed = entries
+                    .into_iter()
+                    .map(|entry| Ok((Value(entry.0.clone()).into_lua(lua)?, entry)))
+                    .collect::<LuaResult<Vec<_>>>()?;
+                merge_sort(keyed, &mut |(a, _), (b, _)| {
+                    comparator.call::<_, bool>((a.clone(), b.clone()))
+                })?
+                .into_iter()
+                .map(|(_, entry)| entry)
+                .collect::<Vec<_>>()
+            }
+            None => sort_by_default_order(entries)?,
+        };
+        // The sorted entries are added to a new list first, so that the original list is only changed
+        // once they are all in place, and is left untouched if adding any of them fails.
+        ByondValue::new_list()
+            .and_then(|mut sorted_list| {
+                add_entries(&mut sorted_list, &sorted)?;
+                this.call::<_, ByondValue, _, ByondValue>("Cut", [])
+                    .and_then(|_| this.call::<_, ByondValue, _, ByondValue>("Add", [sorted_list]))
+            })
+            .map(|_| ())
+            .into_printed_external()
+    }
+
+    /// Creates a new list from the results of calling `callback` with each element of the list and its index.
+    fn map(_: &Lua, (Value(this), callback): (Value, Function)) -> LuaResult<Value> {
+        let (_copy, entries) = read_entries(&this, false)?;
+        entries
+            .into_iter()
+            .zip(1..)
+            .map(|((element, _), index)| {
+                callback
+                    .call::<_, Value>((Value(element), index))
+                    .map(|Value(result)| result)
+            })
+            .collect::<LuaResult<Vec<_>>>()
+            .and_then(|results| results.to_byond().into_printed_external())
             .map(Value)
+    }
+
+    /// Creates a new list with the entries of the list whose elements `predicate` returns a truthy value for.
+    /// Assoc values are kept.
+    fn filter_by(_: &Lua, (Value(this), predicate): (Value, Function)) -> LuaResult<Value> {
+        let (_copy, entries) = read_entries(&this, true)?;
+        let mut kept = vec![];
+        for ((key, value), index) in entries.into_iter().zip(1..) {
+            if predicate.call::<_, bool>((Value(key.clone()), index))? {
+                kept.push((key, value));
+            }
+        }
+        ByondValue::new_list()
+            .and_then(|mut list| add_entries(&mut list, &kept).map(|()| Value(list)))
             .into_printed_external()
     }
+
+    /// Calls `callback` with the accumulated value, each element of the list, and its index, in order.
+    /// Starts with `initial`, or with the first element of the list if `initial` is nil.
+    fn reduce<'lua>(
+        lua: &'lua Lua,
+        (Value(this), callback, initial): (Value, Function<'lua>, LuaValue<'lua>),
+    ) -> LuaResult<LuaValue<'lua>> {
+        let (_copy, entries) = read_entries(&this, false)?;
+        let mut entries = entries.into_iter().zip(1..);
+        let mut accumulator = match initial {
+            LuaValue::Nil => match entries.next() {
+                Some(((first, _), _)) => Value(first).into_lua(lua)?,
+                None => return Ok(LuaValue::Nil),
+            },
+            initial => initial,
+        };
+        for ((element, _), index) in entries {
+            accumulator = callback.call((accumulator, Value(element), index))?;
+        }
+        Ok(accumulator)
+    }
+
+    fn any(_: &Lua, (Value(this), predicate): (Value, Function)) -> LuaResult<bool> {
+        let (_copy, entries) = read_entries(&this, false)?;
+        for ((element, _), index) in entries.into_iter().zip(1..) {
+            if predicate.call::<_, bool>((Value(element), index))? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn all(_: &Lua, (Value(this), predicate): (Value, Function)) -> LuaResult<bool> {
+        let (_copy, entries) = read_entries(&this, false)?;
+        for ((element, _), index) in entries.into_iter().zip(1..) {
+            if !predicate.call::<_, bool>((Value(element), index))? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn count(_: &Lua, (Value(this), predicate): (Value, Function)) -> LuaResult<usize> {
+        let (_copy, entries) = read_entries(&this, false)?;
+        let mut count = 0;
+        for ((element, _), index) in entries.into_iter().zip(1..) {
+            if predicate.call::<_, bool>((Value(element), index))? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn view(
+        lua: &Lua,
+        (Value(this), chunk_size): (Value, Option<usize>),
+    ) -> LuaResult<LuaValue<'_>> {
+        ListView::new(this, chunk_size)
+            .into_printed_external()
+            .and_then(|view| lua.create_userdata(view))
+            .map(LuaValue::UserData)
+    }
+
+    fn pairs(
+        lua: &Lua,
+        (Value(this), chunk_size): (Value, Option<usize>),
+    ) -> LuaResult<Function<'_>> {
+        ListView::new(this, chunk_size)
+            .into_printed_external()?
+            .pairs(lua)
+    }
+
+    /// Creates a new list with the entries of the list whose keys, or assoc values if `by_value` is true, pass `filter`.
+    fn filter(
+        _: &Lua,
+        (Value(this), filter, by_value): (Value, TypeFilter, Option<bool>),
+    ) -> LuaResult<Value> {
+        let by_value = by_value.unwrap_or(false);
+        let (_copy, entries) = read_entries(&this, true)?;
+        let mut kept = vec![];
+        for (key, value) in entries {
+            if filter.matches(if by_value { &value } else { &key })? {
+                kept.push((key, value));
+            }
+        }
+        ByondValue::new_list()
+            .and_then(|mut list| add_entries(&mut list, &kept).map(|()| Value(list)))
+            .into_printed_external()
+    }
+}
+
+/// The types that `list.filter` keeps values of.
+enum TypeFilter {
+    /// Typepaths checked with the same semantics as `istype`, so subtypes of any of them pass.
+    Paths(Vec<String>),
+    /// A list whose keys are typepaths that are all associated with truthy values, such as one created by a typecache proc.
+    /// Only values whose exact type is associated with a truthy value pass.
+    Typecache(ByondObject),
+}
+
+impl TypeFilter {
+    fn path_from_byond(value: &ByondValue) -> LuaResult<String> {
+        if TYPEPATH_TYPES.contains(&value.get_type().0) {
+            value.get_string().into_printed_external()
+        } else {
+            Err(LuaError::external(format!(
+                "expected a typepath, got a {}",
+                type_name_for_obj(value)
+            )))
+        }
+    }
+
+    fn path_from_lua(value: LuaValue) -> LuaResult<String> {
+        match value {
+            LuaValue::String(path) => Ok(path.to_str()?.to_owned()),
+            LuaValue::UserData(ud) if ud.is::<ByondObject>() => {
+                Self::path_from_byond(&ud.borrow::<ByondObject>()?.0)
+            }
+            other => Err(LuaError::external(format!(
+                "expected a typepath, got a {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn matches(&self, value: &ByondValue) -> LuaResult<bool> {
+        match self {
+            Self::Paths(paths) => Ok(paths.iter().any(|path| value.is_type(path))),
+            Self::Typecache(typecache) => {
+                if !value.is_ref() {
+                    return Ok(false);
+                }
+                value
+                    .read_var::<_, ByondValue>("type")
+                    .and_then(|type_| typecache.0.read_list_index::<_, ByondValue>(&type_))
+                    .map(|cached| cached.is_true())
+                    .into_printed_external()
+            }
+        }
+    }
+}
+
+impl<'lua> FromLua<'lua> for TypeFilter {
+    fn from_lua(value: LuaValue<'lua>, _: &'lua Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::UserData(ud) if ud.is::<ByondObject>() => {
+                let object = ud.borrow::<ByondObject>()?;
+                if can_index_by_anything(&object.0) {
+                    let entries = object.0.read_assoc_list().into_printed_external()?;
+                    if !entries.is_empty() && entries.iter().all(|[_, value]| value.is_true()) {
+                        return Ok(Self::Typecache(ByondObject::new(object.0.clone())));
+                    }
+                    // Any other list is a list of typepaths, which are checked like `istype`.
+                    return entries
+                        .iter()
+                        .map(|[path, _]| Self::path_from_byond(path))
+                        .collect::<LuaResult<Vec<_>>>()
+                        .map(Self::Paths);
+                }
+                drop(object);
+                Self::path_from_lua(LuaValue::UserData(ud)).map(|path| Self::Paths(vec![path]))
+            }
+            LuaValue::Table(paths) => paths
+                .sequence_values::<LuaValue>()
+                .map(|path| path.and_then(Self::path_from_lua))
+                .collect::<LuaResult<Vec<_>>>()
+                .map(Self::Paths),
+            other => Self::path_from_lua(other).map(|path| Self::Paths(vec![path])),
+        }
+    }
+}
+
+/// Reads every entry of `list` at once, along with the copy they were read from.
+///
+/// Holding onto the copy keeps the entries alive while lua code runs, even if that code removes them from `list`.
+fn read_entries(
+    list: &ByondValue,
+    assoc: bool,
+) -> LuaResult<(ByondObject, Vec<(ByondValue, ByondValue)>)> {
+    if !list.is_list() {
+        return Err(ByondError::NotAList).into_printed_external();
+    }
+    list.length()
+        .and_then(|length| wrapped_read_list_range(list, 1, length + 1, assoc))
+        .map(|(copy, entries)| (ByondObject::new(copy), entries))
+        .into_printed_external()
+}
+
+/// Appends `entries` to `list` in order, then writes their assoc values.
+///
+/// Writing `list[key] = value` for each entry instead would write out of bounds for numeric elements,
+/// and collapse elements that repeat into a single entry.
+fn add_entries(list: &mut ByondValue, entries: &[(ByondValue, ByondValue)]) -> ByondResult<()> {
+    let elements = entries
+        .iter()
+        .map(|(key, _)| as_list_element(key))
+        .collect::<ByondResult<Vec<_>>>()?;
+    list.call::<_, _, _, ByondValue>("Add", elements)
+        .and_then(|_| {
+            entries
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .try_for_each(|(key, value)| list.write_list_index(key, value))
+        })
+}
+
+/// Sorts entries by their elements when no comparator is given.
+/// Numbers and strings are ordered the same way DM's `<` operator orders them, and cannot be compared with each other.
+fn sort_by_default_order(
+    entries: Vec<(ByondValue, ByondValue)>,
+) -> LuaResult<Vec<(ByondValue, ByondValue)>> {
+    if let Some((unsortable, _)) = entries
+        .iter()
+        .find(|(element, _)| !element.is_number() && !element.is_string())
+    {
+        return Err(LuaError::external(format!(
+            "cannot sort a list containing a {} without a comparator",
+            type_name_for_obj(unsortable)
+        )));
+    }
+    if entries.iter().all(|(element, _)| element.is_number()) {
+        let keyed = entries
+            .into_iter()
+            .map(|entry| entry.0.get_number().map(|number| (number, entry)))
+            .collect::<ByondResult<Vec<_>>>()
+            .into_printed_external()?;
+        return Ok(merge_sort(keyed, &mut |(a, _), (b, _)| Ok(a < b))?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect());
+    }
+    if !entries.iter().all(|(element, _)| element.is_string()) {
+        return Err(LuaError::external(
+            "cannot compare a number with a string without a comparator",
+        ));
+    }
+    // DM's text ordering is not a plain byte-wise comparison, so the strings are sorted by DM itself,
+    // which returns the order of their indices in a single call.
+    let order = entries
+        .iter()
+        .map(|(element, _)| element.clone())
+        .collect::<Vec<_>>()
+        .to_byond()
+        .and_then(|texts| call_global::<_, _, _, Vec<ByondValue>>("_text_sort_order", [texts]))
+        .into_printed_external()?;
+    order
+        .into_iter()
+        .map(|index| {
+            let index = index.get_number().into_printed_external()? as usize;
+            entries
+                .get(index.wrapping_sub(1))
+                .cloned()
+                .ok_or_else(|| LuaError::external("text sort returned an invalid index"))
+        })
+        .collect()
+}
+
+/// A stable merge sort that stops at the first error returned by `less`.
+///
+/// `slice::sort_by` can neither propagate errors nor be relied on when a lua comparator is inconsistent.
+fn merge_sort<T>(
+    mut items: Vec<T>,
+    less: &mut impl FnMut(&T, &T) -> LuaResult<bool>,
+) -> LuaResult<Vec<T>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, less)?.into_iter().peekable();
+    let mut right = merge_sort(right, less)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        let take_right = less(r, l)?;
+        merged.extend(if take_right {
+            right.next()
+        } else {
+            left.next()
+        });
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
 }
 
 impl LuaModule for ListModule {
@@ -173,12 +646,54 @@ impl LuaModule for ListModule {
             ),
             ("splice", Function::wrap(Self::splice).into_lua(lua)?),
             ("swap", Function::wrap(Self::swap).into_lua(lua)?),
+            ("sort", Function::wrap(Self::sort).into_lua(lua)?),
+            (
+                "table_insert",
+                Function::wrap(Self::table_insert).into_lua(lua)?,
+            ),
+            (
+                "table_remove",
+                Function::wrap(Self::table_remove).into_lua(lua)?,
+            ),
+            (
+                "table_concat",
+                Function::wrap(Self::table_concat).into_lua(lua)?,
+            ),
+            ("new", Function::wrap(Self::new_list).into_lua(lua)?),
+            ("of", Function::wrap(Self::of).into_lua(lua)?),
+            ("assoc", Function::wrap(Self::assoc).into_lua(lua)?),
+            ("union", Function::wrap(Self::union).into_lua(lua)?),
+            ("intersect", Function::wrap(Self::intersect).into_lua(lua)?),
+            (
+                "difference",
+                Function::wrap(Self::difference).into_lua(lua)?,
+            ),
+            (
+                "symmetric_difference",
+                Function::wrap(Self::symmetric_difference).into_lua(lua)?,
+            ),
+            ("map", Function::wrap(Self::map).into_lua(lua)?),
+            ("filter_by", Function::wrap(Self::filter_by).into_lua(lua)?),
+            ("reduce", Function::wrap(Self::reduce).into_lua(lua)?),
+            ("any", Function::wrap(Self::any).into_lua(lua)?),
+            ("all", Function::wrap(Self::all).into_lua(lua)?),
+            ("count", Function::wrap(Self::count).into_lua(lua)?),
             (
                 "from_table",
                 Function::wrap(convert_from_table).into_lua(lua)?,
             ),
             ("to_table", Function::wrap(convert_to_table).into_lua(lua)?),
+            (
+                "from_pairs",
+                Function::wrap(convert_from_pairs).into_lua(lua)?,
+            ),
             ("filter", Function::wrap(Self::filter).into_lua(lua)?),
+            ("view", Function::wrap(Self::view).into_lua(lua)?),
+            ("pairs", Function::wrap(Self::pairs).into_lua(lua)?),
+            (
+                "ordered",
+                Function::wrap(create_ordered_table).into_lua(lua)?,
+            ),
         ])
     }
 
@@ -5,13 +5,18 @@ use mlua::{
     Function, IntoLua, Lua, MetaMethod, Table,
 };
 
+mod buffer;
+mod coroutine;
 mod dm;
 mod exec;
 mod global;
 mod global_procs;
+mod json;
 mod list;
 mod package;
 mod pointer;
+pub use buffer::BufferModule;
+pub use coroutine::CoroutineModule;
 pub use global::GlobalModule;
 pub use package::PackageModule;
 
@@ -1,10 +1,10 @@
 use std::cell::RefCell;
 use std::error::Error;
-use std::ptr;
 use std::rc::Rc;
+use std::time::Duration;
 
 use meowtonin::{byond_fn, ByondError, ByondResult, ByondValue, ToByond};
-use mlua::{Compiler, Lua, Table};
+use mlua::{prelude::LuaError, Compiler, Lua, Table};
 
 use dreamluau_proc_macro::map_statics;
 use exec_limit::limiting_interrupt;
@@ -12,20 +12,27 @@ use exec_limit::limiting_interrupt;
 use crate::cache::global_proc::GlobalFnMap;
 use crate::cache::object_proc::ObjectFnMap;
 use crate::cache::userdata::drop_cached_userdata;
-use crate::value::{safe_convert_from_table, Value};
+use crate::value::{safe_convert_from_table, Value, LIST_APPEND, VECTOR_LISTS};
 
-use self::library::{GlobalModule, LuaModule, PackageModule};
+use self::library::{BufferModule, CoroutineModule, GlobalModule, LuaModule, PackageModule};
+use self::options::RunOptions;
 use self::threads::{
-    get_yielded_thread, nuke_main_chunks, remove_sleeping_thread, ThreadList, Threads,
+    discard_thread, get_yielded_thread, nuke_main_chunks, remove_sleeping_thread,
+    remove_sleeping_thread_by_id, remove_tagged_threads, ThreadList, Threads,
 };
-use self::util::entrypoint::{get_entrypoint, remove_main_chunk};
 use self::util::prepare_registry_functions;
-pub use exec_limit::{clear_execution_limit, set_execution_limit_millis, set_execution_limit_secs};
+pub(crate) use exec_limit::check_execution_limit;
+pub use exec_limit::{
+    clear_execution_limit, clear_max_call_depth, set_execution_limit_millis,
+    set_execution_limit_secs, set_max_call_depth,
+};
 pub use usr::set_usr;
 pub use util::traceback::get_traceback;
 
+mod context;
 mod exec_limit;
 mod library;
+mod options;
 mod run;
 mod sleep;
 mod threads;
@@ -51,6 +58,10 @@ pub fn new_state(isolate: Option<bool>) -> ByondResult<usize> {
         .populate_table(&lua.globals(), &lua)
         .and_then(|()| lua.globals().raw_get::<_, Table>("package"))
         .and_then(|package| PackageModule.populate_table(&package, &lua))
+        .and_then(|()| lua.globals().raw_get::<_, Table>("coroutine"))
+        .and_then(|coroutine| CoroutineModule.populate_table(&coroutine, &lua))
+        .and_then(|()| lua.globals().raw_get::<_, Table>("buffer"))
+        .and_then(|buffer| BufferModule.populate_table(&buffer, &lua))
         .and_then(|()| prepare_registry_functions(&lua))
         .and_then(|()| lua.sandbox(true))
         .map_err(ByondError::boxed)?;
@@ -80,14 +91,67 @@ pub fn is_isolated(index: usize) -> ByondResult<bool> {
     })
 }
 
+/// Sets whether lists of exactly 3 numbers returned from procs are converted to luau vectors for the state at `index`.
+#[byond_fn]
+pub fn set_vector_conversion(index: usize, enabled: bool) -> ByondResult<()> {
+    get_state(index).and_then(|lua| {
+        lua.set_named_registry_value(VECTOR_LISTS, enabled)
+            .map_err(ByondError::boxed)
+    })
+}
+
+/// Sets whether writing to the index just past the end of a list appends to the list, for the state at `index`.
+#[byond_fn]
+pub fn set_list_append(index: usize, enabled: bool) -> ByondResult<()> {
+    get_state(index).and_then(|lua| {
+        lua.set_named_registry_value(LIST_APPEND, enabled)
+            .map_err(ByondError::boxed)
+    })
+}
+
+/// Contexts are exposed to luau by converting them to a table, so anything other than a list is rejected up front.
+fn check_context(context: &Option<ByondValue>) -> ByondResult<()> {
+    match context {
+        Some(context) if !context.is_list() => Err(ByondError::NotAList),
+        _ => Ok(()),
+    }
+}
+
 #[byond_fn]
-pub fn load(index: usize, code: String, name: Option<String>) -> ByondResult<ByondValue> {
-    get_state(index).and_then(|lua| run::load(lua.as_ref(), code, name))
+pub fn load(
+    index: usize,
+    code: String,
+    name: Option<String>,
+    options: Option<RunOptions>,
+) -> ByondResult<ByondValue> {
+    get_state(index)
+        .and_then(|lua| run::load(lua.as_ref(), code, name, options.unwrap_or_default()))
 }
 
 #[byond_fn]
-pub fn awaken(index: usize) -> ByondResult<ByondValue> {
-    get_state(index).and_then(|lua| run::awaken(lua.as_ref()))
+pub fn awaken(index: usize, context: Option<ByondValue>) -> ByondResult<ByondValue> {
+    check_context(&context)?;
+    get_state(index).and_then(|lua| run::awaken(lua.as_ref(), context))
+}
+
+#[byond_fn]
+pub fn awaken_group(index: usize, tag: String) -> ByondResult<ByondValue> {
+    get_state(index).and_then(|lua| run::awaken_group(lua.as_ref(), tag))
+}
+
+#[byond_fn]
+pub fn awaken_thread(
+    state_index: usize,
+    thread_id: usize,
+    args: Option<Vec<Value>>,
+) -> ByondResult<ByondValue> {
+    get_state(state_index)
+        .and_then(|lua| run::awaken_thread(lua.as_ref(), thread_id, args.unwrap_or_default()))
+}
+
+#[byond_fn]
+pub fn awaken_all(index: usize) -> ByondResult<ByondValue> {
+    get_state(index).and_then(|lua| run::awaken_all(lua.as_ref()))
 }
 
 #[byond_fn]
@@ -95,13 +159,31 @@ pub fn resume(
     state_index: usize,
     thread_index: usize,
     args: Vec<Value>,
+    context: Option<ByondValue>,
 ) -> ByondResult<ByondValue> {
-    get_state(state_index).and_then(|lua| run::resume(lua.as_ref(), thread_index, args))
+    check_context(&context)?;
+    get_state(state_index).and_then(|lua| run::resume(lua.as_ref(), thread_index, args, context))
 }
 
 #[byond_fn]
-pub fn call_function(index: usize, path: Vec<Value>, args: Vec<Value>) -> ByondResult<ByondValue> {
-    get_state(index).and_then(|lua| run::call(lua.as_ref(), path, args))
+pub fn resume_with_error(
+    state_index: usize,
+    thread_index: usize,
+    message: String,
+) -> ByondResult<ByondValue> {
+    get_state(state_index)
+        .and_then(|lua| run::resume_with_error(lua.as_ref(), thread_index, message))
+}
+
+#[byond_fn]
+pub fn call_function(
+    index: usize,
+    path: Vec<Value>,
+    args: Vec<Value>,
+    options: Option<RunOptions>,
+) -> ByondResult<ByondValue> {
+    get_state(index)
+        .and_then(|lua| run::call(lua.as_ref(), path, args, options.unwrap_or_default()))
 }
 
 #[byond_fn]
@@ -121,8 +203,8 @@ pub fn get_globals(index: usize) -> ByondResult<Value> {
 }
 
 #[byond_fn]
-pub fn list_threads(index: usize) -> ByondResult<ThreadList> {
-    get_state(index).and_then(|lua| threads::list_threads(lua.as_ref()))
+pub fn list_threads(index: usize, tag: Option<String>) -> ByondResult<ThreadList> {
+    get_state(index).and_then(|lua| threads::list_threads(lua.as_ref(), tag))
 }
 
 #[byond_fn]
@@ -133,16 +215,25 @@ pub fn collect_garbage(index: usize) -> ByondResult<()> {
     })
 }
 
+/// Killing a thread succeeds even if its callback raises an error,
+/// but the error is still returned so that it does not go unnoticed.
+fn report_callback_error(callback_error: Option<String>) -> ByondResult<()> {
+    match callback_error {
+        Some(e) => Err(ByondError::boxed(LuaError::external(format!(
+            "the thread was removed, but its callback raised an error: {e}"
+        )))),
+        None => Ok(()),
+    }
+}
+
 #[byond_fn]
 pub fn kill_yielded_thread(state_index: usize, thread_index: usize) -> ByondResult<()> {
     get_state(state_index).and_then(|lua| {
         get_yielded_thread(lua.as_ref(), thread_index)
-            .map(|thread| {
-                remove_main_chunk(
-                    &get_entrypoint(lua.as_ref(), &thread.thread).unwrap_or(ptr::null()),
-                )
-            })
             .map_err(ByondError::boxed)
+            .and_then(|thread| {
+                report_callback_error(discard_thread(lua.as_ref(), &thread, "killed"))
+            })
     })
 }
 
@@ -150,12 +241,50 @@ pub fn kill_yielded_thread(state_index: usize, thread_index: usize) -> ByondResu
 pub fn kill_sleeping_thread(state_index: usize, thread_index: usize) -> ByondResult<()> {
     get_state(state_index).and_then(|lua| {
         remove_sleeping_thread(lua.as_ref(), thread_index)
-            .map(|thread| {
-                remove_main_chunk(
-                    &get_entrypoint(lua.as_ref(), &thread.thread).unwrap_or(ptr::null()),
-                )
+            .map_err(ByondError::boxed)
+            .and_then(|thread| {
+                report_callback_error(discard_thread(lua.as_ref(), &thread, "killed"))
             })
+    })
+}
+
+#[byond_fn]
+pub fn kill_sleeping_thread_by_id(state_index: usize, thread_id: usize) -> ByondResult<()> {
+    get_state(state_index).and_then(|lua| {
+        remove_sleeping_thread_by_id(lua.as_ref(), thread_id)
+            .map_err(ByondError::boxed)
+            .and_then(|thread| {
+                report_callback_error(discard_thread(lua.as_ref(), &thread, "killed"))
+            })
+    })
+}
+
+#[byond_fn]
+pub fn prioritize_sleeping_thread(state_index: usize, thread_id: usize) -> ByondResult<()> {
+    get_state(state_index).and_then(|lua| {
+        threads::prioritize_sleeping_thread(lua.as_ref(), thread_id).map_err(ByondError::boxed)
+    })
+}
+
+#[byond_fn]
+pub fn kill_thread_group(state_index: usize, tag: String) -> ByondResult<usize> {
+    get_state(state_index).and_then(|lua| {
+        let removed = remove_tagged_threads(lua.as_ref(), &tag);
+        // Every thread is discarded before any callback error is reported, so none are left half-removed.
+        let callback_errors = removed
+            .iter()
+            .filter_map(|thread| discard_thread(lua.as_ref(), thread, "killed"))
+            .collect::<Vec<_>>();
+        report_callback_error(callback_errors.into_iter().next()).map(|()| removed.len())
+    })
+}
+
+#[byond_fn]
+pub fn reap_expired_threads(index: usize) -> ByondResult<ByondValue> {
+    get_state(index).and_then(|lua| {
+        threads::reap_expired_threads(lua.as_ref())
             .map_err(ByondError::boxed)
+            .and_then(|expired| run::expired_thread_list(&expired))
     })
 }
 
@@ -233,3 +362,31 @@ pub fn clear_state_execution_limit(index: usize) -> ByondResult<()> {
             .map_err(ByondError::boxed)
     })
 }
+
+#[byond_fn]
+pub fn set_yield_timeout_millis(index: usize, new_timeout: u32) -> ByondResult<()> {
+    get_state(index).and_then(|state| {
+        state
+            .set_named_registry_value("yield_timeout", new_timeout as f32 / 1000.0)
+            .map_err(ByondError::boxed)
+    })
+}
+
+#[byond_fn]
+pub fn set_yield_timeout_secs(index: usize, new_timeout: f32) -> ByondResult<()> {
+    Duration::try_from_secs_f32(new_timeout).map_err(ByondError::boxed)?;
+    get_state(index).and_then(|state| {
+        state
+            .set_named_registry_value("yield_timeout", new_timeout)
+            .map_err(ByondError::boxed)
+    })
+}
+
+#[byond_fn]
+pub fn clear_yield_timeout(index: usize) -> ByondResult<()> {
+    get_state(index).and_then(|state| {
+        state
+            .unset_named_registry_value("yield_timeout")
+            .map_err(ByondError::boxed)
+    })
+}
@@ -0,0 +1,56 @@
+use std::error::Error;
+
+use meowtonin::{ByondError, ByondResult, ByondValue, FromByond};
+
+use super::{check_context, threads::ThreadCallback};
+
+/// Options for the threads started by `load` and `call_function`, passed from DM as an associative list.
+pub struct RunOptions {
+    pub tag: Option<String>,
+    pub callback: Option<ThreadCallback>,
+    pub capture_usr: bool,
+    pub context: Option<ByondValue>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            tag: None,
+            callback: None,
+            capture_usr: true,
+            context: None,
+        }
+    }
+}
+
+impl FromByond for RunOptions {
+    fn from_byond(value: ByondValue) -> ByondResult<Self> {
+        if !value.is_list() {
+            return Err(ByondError::NotAList);
+        }
+        let mut options = Self::default();
+        let mut callback: Option<String> = None;
+        let mut callback_arg = ByondValue::NULL;
+        for (key, entry) in value.iter()? {
+            match String::from_byond(key)?.as_str() {
+                "tag" => options.tag = FromByond::from_byond(entry)?,
+                "callback" => callback = FromByond::from_byond(entry)?,
+                "callback_arg" => callback_arg = entry,
+                "capture_usr" => {
+                    options.capture_usr = Option::<bool>::from_byond(entry)?.unwrap_or(true)
+                }
+                "context" => {
+                    options.context = FromByond::from_byond(entry)?;
+                    check_context(&options.context)?
+                }
+                other => {
+                    return Err(ByondError::Boxed(Box::<dyn Error + Send + Sync>::from(
+                        format!("Unknown option \"{other}\""),
+                    )))
+                }
+            }
+        }
+        options.callback = callback.map(|proc| ThreadCallback::new(proc, callback_arg));
+        Ok(options)
+    }
+}
@@ -1,17 +1,27 @@
+use std::error::Error;
 use std::ptr;
 
 use meowtonin::{ByondError, ByondResult, ByondValue, ToByond};
 use mlua::{
-    prelude::{LuaError, LuaValue},
-    FromLua, IntoLuaMulti, Lua, ThreadStatus, Variadic,
+    prelude::{LuaError, LuaResult, LuaValue},
+    Function, IntoLuaMulti, Lua, MultiValue, ThreadStatus, Variadic,
 };
 
-use crate::value::{safe_convert_from_table, ByondObject, ConversionVariant, Value};
+use crate::value::{
+    apply_todm_hook, convert_value, safe_convert_from_table, ByondObject, ConversionVariant, Value,
+};
 
 use super::{
-    exec_limit::{decrement_call_depth, increment_call_depth},
-    threads::{get_yielded_thread, pop_front_sleeping_thread, push_yielded_thread, NamedThread},
-    usr::{pop_usr, push_usr},
+    context::{pop_context, push_context},
+    exec_limit::{check_call_depth, decrement_call_depth, get_call_depth, increment_call_depth},
+    options::RunOptions,
+    threads::{
+        create_tagged_environment, get_function_tag, get_yielded_thread, peek_thread_name,
+        pop_front_sleeping_thread, pop_front_tagged_sleeping_thread, push_yielded_thread,
+        reap_expired_threads, remove_sleeping_thread_by_id, sleeping_thread_ids, thread_info,
+        ExpiredThread, NamedThread, Suspension, ThreadLocation,
+    },
+    usr::{pop_usr, push_usr, take_usr},
     util::{
         entrypoint::{get_entrypoint, insert_main_chunk, remove_main_chunk},
         traceback::{pop_traceback_func, push_traceback_func},
@@ -24,32 +34,9 @@ pub fn process_return_values(
 ) -> ByondResult<Vec<(&'static str, ByondValue)>> {
     let (values, variants): (Vec<Value>, Vec<ConversionVariant>) = return_values
         .into_iter()
-        .map(|value| match value {
-            LuaValue::Table(table) => safe_convert_from_table(lua, table).unwrap(),
-            LuaValue::Function(ref f) => (
-                Value::from(format!(
-                    "{}: {:p}",
-                    f.info().name.unwrap_or(String::from("anonymous function")),
-                    value.to_pointer()
-                )),
-                ConversionVariant::Function,
-            ),
-            LuaValue::Thread(_) => (
-                Value::from(format!("{:p}", value.to_pointer())),
-                ConversionVariant::Thread,
-            ),
-            LuaValue::UserData(ref ud) if !ud.is::<ByondObject>() => (
-                Value::from(format!("{:p}", value.to_pointer())),
-                ConversionVariant::Userdata,
-            ),
-            LuaValue::Error(e) => (Value::from(e.to_string()), ConversionVariant::ErrorAsValue),
-            anything_else => match Value::from_lua(anything_else, lua) {
-                Ok(v) => (v, ConversionVariant::None),
-                Err(e) => (
-                    Value(e.to_string().to_byond().unwrap()),
-                    ConversionVariant::ConversionError,
-                ),
-            },
+        .map(|value| match apply_todm_hook(value) {
+            Ok(value) => convert_return_value(lua, value),
+            Err(e) => conversion_error(e),
         })
         .collect::<Vec<_>>()
         .into_iter()
@@ -60,20 +47,138 @@ pub fn process_return_values(
     ])
 }
 
+/// The entry for a return value that failed to convert, which is replaced with its error message.
+fn conversion_error(e: LuaError) -> (Value, ConversionVariant) {
+    (
+        Value(e.to_string().to_byond().unwrap()),
+        ConversionVariant::ConversionError,
+    )
+}
+
+fn convert_return_value(lua: &Lua, value: LuaValue) -> (Value, ConversionVariant) {
+    match value {
+        // Safe conversion still fails outright if the table exceeds the conversion or execution limits.
+        LuaValue::Table(table) => {
+            safe_convert_from_table(lua, table).unwrap_or_else(conversion_error)
+        }
+        LuaValue::Function(ref f) => (
+            Value::from(format!(
+                "{}: {:p}",
+                f.info().name.unwrap_or(String::from("anonymous function")),
+                value.to_pointer()
+            )),
+            ConversionVariant::Function,
+        ),
+        LuaValue::Thread(_) => (
+            Value::from(format!("{:p}", value.to_pointer())),
+            ConversionVariant::Thread,
+        ),
+        LuaValue::UserData(ref ud) if !ud.is::<ByondObject>() && !value.is_buffer() => (
+            Value::from(format!("{:p}", value.to_pointer())),
+            ConversionVariant::Userdata,
+        ),
+        LuaValue::Error(e) => (Value::from(e.to_string()), ConversionVariant::ErrorAsValue),
+        anything_else => match convert_value(anything_else, lua) {
+            Ok(v) => (v, ConversionVariant::None),
+            Err(e) => conversion_error(e),
+        },
+    }
+}
+
+pub fn expired_thread_list(expired: &[ExpiredThread]) -> ByondResult<ByondValue> {
+    expired
+        .iter()
+        .map(|(index, thread, callback_error)| {
+            let mut info = thread_info(*index, thread)?;
+            if let Some(callback_error) = callback_error {
+                info.push(("callback_error", callback_error.to_byond()?));
+            }
+            Ok(info)
+        })
+        .collect::<ByondResult<Vec<_>>>()
+        .and_then(|list| list.to_byond())
+}
+
+/// Resumes the thread with an error raised from the point where it yielded,
+/// as if the `coroutine.yield` call that yielded it had raised that error.
+fn resume_with_error_value<'lua>(
+    lua: &'lua Lua,
+    thread: &NamedThread,
+    message: String,
+) -> LuaResult<Variadic<LuaValue<'lua>>> {
+    lua.named_registry_value::<Function>("resume_error")
+        .and_then(|f| f.call::<_, MultiValue>((&thread.thread, message)))
+        .and_then(|results| {
+            let mut results = results.into_iter();
+            match results.next() {
+                Some(LuaValue::Boolean(true)) => Ok(results.collect()),
+                _ => Err(match results.next() {
+                    Some(LuaValue::Error(e)) => e,
+                    Some(other) => LuaError::runtime(other.to_string()?),
+                    None => LuaError::runtime("thread raised an unknown error"),
+                }),
+            }
+        })
+}
+
+/// Undoes everything pushed to run a thread when dropped,
+/// so that the call depth, `usr`, context, and traceback function stacks are restored even if running the thread panics.
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        decrement_call_depth();
+        pop_usr();
+        pop_context();
+        pop_traceback_func();
+    }
+}
+
 pub fn run_thread<'lua, A: IntoLuaMulti<'lua> + Clone>(
     lua: &'lua Lua,
     thread: &'lua NamedThread,
     args: A,
 ) -> ByondResult<ByondValue> {
+    run_thread_with(lua, thread, |thread| {
+        thread.thread.resume::<A, Variadic<LuaValue>>(args)
+    })
+}
+
+fn run_thread_with<'lua, F>(
+    lua: &'lua Lua,
+    thread: &'lua NamedThread,
+    resume_fn: F,
+) -> ByondResult<ByondValue>
+where
+    F: FnOnce(&'lua NamedThread) -> LuaResult<Variadic<LuaValue<'lua>>>,
+{
     let name = thread.name.clone();
-    push_traceback_func(lua, &thread.thread).map_err(ByondError::boxed)?;
-    push_usr();
-    increment_call_depth();
-    let result = thread.thread.resume::<A, Variadic<LuaValue>>(args);
-    decrement_call_depth();
-    pop_usr();
-    pop_traceback_func();
-    if thread.thread.status() != ThreadStatus::Resumable {
+    // Expired threads are only reaped by the outermost call,
+    // so that they are not removed in the middle of another thread's execution.
+    let expired = if get_call_depth() == 0 {
+        reap_expired_threads(lua).map_err(ByondError::boxed)?
+    } else {
+        vec![]
+    };
+    let current_usr = take_usr();
+    // Suspended threads are checked against the call depth before they are taken,
+    // so only new threads can fail this check.
+    let result = check_call_depth(&name).and_then(|()| {
+        push_traceback_func(lua, &thread.thread)?;
+        push_context(thread.context.clone());
+        push_usr(if thread.capture_usr {
+            thread.usr.clone()
+        } else {
+            current_usr
+        });
+        increment_call_depth(name.clone());
+        let _run_guard = RunGuard;
+        // Return values are converted before the call depth is decremented,
+        // so that converting them is still subject to the execution limit.
+        resume_fn(thread).map(|return_values| process_return_values(lua, return_values))
+    });
+    let finished = result.is_err() || thread.thread.status() != ThreadStatus::Resumable;
+    if finished {
         remove_main_chunk(&get_entrypoint(lua, &thread.thread).unwrap_or(ptr::null()))
     }
     let mut output = match result {
@@ -81,59 +186,210 @@ pub fn run_thread<'lua, A: IntoLuaMulti<'lua> + Clone>(
             ThreadStatus::Unresumable => vec![("status", "finished".to_byond().unwrap())],
             ThreadStatus::Resumable => {
                 match push_yielded_thread(lua, thread.to_owned()).map_err(ByondError::boxed)? {
-                    Some(index) => vec![
+                    Suspension::Yield(index) => vec![
                         ("status", "yield".to_byond().unwrap()),
                         ("index", index.to_byond()?),
                     ],
-                    None => vec![("status", "sleep".to_byond().unwrap())],
+                    Suspension::Sleep(id) => vec![
+                        ("status", "sleep".to_byond().unwrap()),
+                        ("id", id.to_byond()?),
+                    ],
                 }
             }
             ThreadStatus::Error => unreachable!("Lua threads that raise an error during execution should not return Ok from resume."),
-        }.into_iter().chain(process_return_values(lua, return_values)?).collect(),
+        }.into_iter().chain(return_values?).collect(),
         Err(e) => vec![
             ("status", "error".to_byond().unwrap()),
             ("message", e.to_string().to_byond().unwrap()),
         ],
     };
     output.extend([("name", name.to_byond().unwrap())]);
-    output.to_byond()
+    if !expired.is_empty() {
+        output.extend([("expired", expired_thread_list(&expired)?)]);
+    }
+    let mut output = output.to_byond()?;
+    if let Some(callback) = thread.callback.as_ref().filter(|_| finished) {
+        if let Some(callback_error) = callback.invoke(&output) {
+            output.write_list_index("callback_error", callback_error)?;
+        }
+    }
+    Ok(output)
 }
 
-pub fn load(lua: &Lua, code: String, name: Option<String>) -> ByondResult<ByondValue> {
+pub fn load(
+    lua: &Lua,
+    code: String,
+    name: Option<String>,
+    RunOptions {
+        tag,
+        callback,
+        capture_usr,
+        context,
+    }: RunOptions,
+) -> ByondResult<ByondValue> {
     let name = name.unwrap_or("input".into());
-    lua.load(code)
-        .set_name(&name)
-        .into_function()
+    tag.as_deref()
+        .map(|tag| create_tagged_environment(lua, tag))
+        .transpose()
+        .and_then(|environment| {
+            let chunk = lua.load(code).set_name(&name);
+            match environment {
+                Some(environment) => chunk.set_environment(environment),
+                None => chunk,
+            }
+            .into_function()
+        })
         .and_then(|func| {
             insert_main_chunk(&func);
             lua.create_thread(func)
         })
         .map_err(ByondError::boxed)
         .and_then(|thread| {
+            // Only taken once the chunk compiles, so that a chunk with a syntax error leaves the pending usr in place.
+            let usr = capture_usr.then(take_usr).flatten();
             run_thread(
                 lua,
                 &NamedThread {
                     name,
+                    tag,
                     thread: thread.into_owned(),
+                    yielded_at: None,
+                    callback,
+                    id: None,
+                    usr,
+                    capture_usr,
+                    context,
                 },
                 (),
             )
         })
 }
 
-pub fn awaken(lua: &Lua) -> ByondResult<ByondValue> {
+/// Replaces the context of a thread that is being awakened or resumed, if a new one was passed in.
+fn replace_context(mut thread: NamedThread, context: Option<ByondValue>) -> NamedThread {
+    if context.is_some() {
+        thread.context = context;
+    }
+    thread
+}
+
+/// Builds the result of a thread that could not be run.
+fn error_result(name: &str, message: String) -> ByondResult<ByondValue> {
+    vec![
+        ("status", "error".to_byond()?),
+        ("message", message.to_byond()?),
+        ("name", name.to_byond()?),
+    ]
+    .to_byond()
+}
+
+/// Checks whether the suspended thread at `location` can be run without exceeding the maximum call depth,
+/// before it is taken from storage. If it cannot, the thread is left in place and an error result is returned for it.
+fn check_suspended_call_depth(
+    lua: &Lua,
+    location: ThreadLocation,
+) -> Option<ByondResult<ByondValue>> {
+    let name = peek_thread_name(lua, location)?;
+    check_call_depth(&name)
+        .err()
+        .map(|e| error_result(&name, e.to_string()))
+}
+
+pub fn awaken(lua: &Lua, context: Option<ByondValue>) -> ByondResult<ByondValue> {
+    if let Some(result) = check_suspended_call_depth(lua, ThreadLocation::FrontSleeping) {
+        return result;
+    }
     pop_front_sleeping_thread(lua)
+        .map(|thread| replace_context(thread, context))
         .map_err(ByondError::boxed)
         .and_then(|thread| run_thread(lua, &thread, ()))
 }
 
-pub fn resume(lua: &Lua, index: usize, args: Vec<Value>) -> ByondResult<ByondValue> {
+pub fn awaken_group(lua: &Lua, tag: String) -> ByondResult<ByondValue> {
+    if let Some(result) = check_suspended_call_depth(lua, ThreadLocation::FrontTaggedSleeping(&tag))
+    {
+        return result;
+    }
+    pop_front_tagged_sleeping_thread(lua, &tag)
+        .map_err(ByondError::boxed)
+        .and_then(|thread| run_thread(lua, &thread, ()))
+}
+
+pub fn awaken_thread(lua: &Lua, id: usize, args: Vec<Value>) -> ByondResult<ByondValue> {
+    if let Some(result) = check_suspended_call_depth(lua, ThreadLocation::SleepingId(id)) {
+        return result;
+    }
+    remove_sleeping_thread_by_id(lua, id)
+        .map_err(ByondError::boxed)
+        .and_then(|thread| run_thread(lua, &thread, Variadic::from_iter(args)))
+}
+
+/// Awakens every thread in the sleep queue, returning a list of their results in queue order.
+///
+/// Threads that go back to sleep are queued again, but are not awakened a second time by the same call.
+/// Each thread is only taken from the queue right before it is run, so threads that are still waiting their turn
+/// can be listed or killed by the threads run before them. Threads killed that way are skipped.
+/// Every thread is run, even if running an earlier one failed;
+/// such a failure is reported as an "error" result in that thread's place.
+/// If the maximum call depth would be exceeded, no threads are taken, and the error is returned instead.
+pub fn awaken_all(lua: &Lua) -> ByondResult<ByondValue> {
+    if let Some(name) = peek_thread_name(lua, ThreadLocation::FrontSleeping) {
+        check_call_depth(&name).map_err(ByondError::boxed)?;
+    }
+    sleeping_thread_ids(lua)
+        .into_iter()
+        .filter_map(|id| remove_sleeping_thread_by_id(lua, id).ok())
+        .map(|thread| {
+            run_thread(lua, &thread, ()).or_else(|e| error_result(&thread.name, e.to_string()))
+        })
+        .collect::<ByondResult<Vec<_>>>()
+        .and_then(|results| results.to_byond())
+}
+
+pub fn resume(
+    lua: &Lua,
+    index: usize,
+    args: Vec<Value>,
+    context: Option<ByondValue>,
+) -> ByondResult<ByondValue> {
+    if let Some(result) = check_suspended_call_depth(lua, ThreadLocation::Yielded(index)) {
+        return result;
+    }
     get_yielded_thread(lua, index)
+        .map(|thread| replace_context(thread, context))
         .map_err(ByondError::boxed)
         .and_then(|thread| run_thread(lua, &thread, Variadic::from_iter(args)))
 }
 
-pub fn call(lua: &Lua, path: Vec<Value>, args: Vec<Value>) -> ByondResult<ByondValue> {
+pub fn resume_with_error(lua: &Lua, index: usize, message: String) -> ByondResult<ByondValue> {
+    if let Some(result) = check_suspended_call_depth(lua, ThreadLocation::Yielded(index)) {
+        return result;
+    }
+    get_yielded_thread(lua, index)
+        .map_err(ByondError::boxed)
+        .and_then(|thread| {
+            run_thread_with(lua, &thread, |thread| {
+                resume_with_error_value(lua, thread, message)
+            })
+        })
+}
+
+pub fn call(
+    lua: &Lua,
+    path: Vec<Value>,
+    args: Vec<Value>,
+    RunOptions {
+        tag,
+        callback,
+        capture_usr,
+        context,
+    }: RunOptions,
+) -> ByondResult<ByondValue> {
+    if tag.is_some() {
+        return Err(ByondError::Boxed(Box::<dyn Error + Send + Sync>::from(
+            "Called functions take the tag of the chunk that defined them",
+        )));
+    }
     let path_length = path.len();
     let mut path = path.into_iter().zip(1..=path_length);
     path.try_fold(LuaValue::Table(lua.globals()), |value, (part, index)| {
@@ -159,13 +415,21 @@ pub fn call(lua: &Lua, path: Vec<Value>, args: Vec<Value>) -> ByondResult<ByondV
     .and_then(|value| {
         let value_pointer = value.to_pointer();
         if let LuaValue::Function(func) = value {
-            let name = func
-                .info()
+            let info = func.info();
+            let name = info
                 .name
                 .unwrap_or_else(|| format!("Function: {:p}", value_pointer));
+            let tag = get_function_tag(lua, &func)?;
             lua.create_thread(func).map(|thread| NamedThread {
                 name,
+                tag,
                 thread: thread.into_owned(),
+                yielded_at: None,
+                callback,
+                id: None,
+                usr: capture_usr.then(take_usr).flatten(),
+                capture_usr,
+                context,
             })
         } else {
             unreachable!()
@@ -1,24 +1,45 @@
 use dreamluau_proc_macro::map_statics;
-use mlua::lua_State;
+use mlua::{ffi::lua_gettop, lua_State};
 use std::{cell::RefCell, os::raw::c_int};
 
 extern "C" {
     fn lua_yield(lua: *mut lua_State, n_results: c_int) -> c_int;
 }
 
+/// The reason the most recently suspended coroutine yielded, if it was suspended by one of the functions in this module.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum YieldKind {
+    Sleep,
+    ToDm,
+}
+
+impl YieldKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            YieldKind::Sleep => "sleep",
+            YieldKind::ToDm => "yield",
+        }
+    }
+}
+
 thread_local! {
-    static SLEEP_FLAG: RefCell<bool> = const { RefCell::new(false) }
+    static YIELD_FLAG: RefCell<Option<YieldKind>> = const { RefCell::new(None) }
 }
 
-#[map_statics(mut SLEEP_FLAG)]
+#[map_statics(mut YIELD_FLAG)]
 pub unsafe extern "C-unwind" fn sleep(lua: *mut lua_State) -> c_int {
-    *sleep_flag = true;
+    yield_flag.replace(YieldKind::Sleep);
     lua_yield(lua, 0)
 }
 
-#[map_statics(mut SLEEP_FLAG)]
-pub fn take_sleep_flag() -> bool {
-    let ret = *sleep_flag;
-    *sleep_flag = false;
-    ret
+/// Yields the passed in values back to DM, even from within a nested coroutine.
+#[map_statics(mut YIELD_FLAG)]
+pub unsafe extern "C-unwind" fn yield_to_dm(lua: *mut lua_State) -> c_int {
+    yield_flag.replace(YieldKind::ToDm);
+    lua_yield(lua, lua_gettop(lua))
+}
+
+#[map_statics(mut YIELD_FLAG)]
+pub fn take_yield_flag() -> Option<YieldKind> {
+    yield_flag.take()
 }
@@ -1,27 +1,86 @@
 use mlua::OwnedThread;
 
+/// A global proc to call with the result of a thread once it finishes or errors, along with an extra argument to pass to it.
+#[derive(Clone)]
+pub struct ThreadCallback {
+    pub(crate) proc: String,
+    pub(crate) arg: ByondValue,
+}
+
+impl ThreadCallback {
+    pub fn new(proc: String, arg: ByondValue) -> Self {
+        Self {
+            proc: match proc.strip_prefix("/proc/") {
+                Some(stripped) => stripped.to_string(),
+                None => proc,
+            },
+            arg,
+        }
+    }
+
+    /// Calls the callback proc with the arguments `(result, arg)`, through the global call wrapper if one is set.
+    ///
+    /// The thread has already finished by the time the callback is invoked,
+    /// so an error raised by the proc is returned to be reported alongside the thread's result.
+    pub fn invoke(&self, result: &ByondValue) -> Option<String> {
+        wrapped_global_call(&self.proc, [result, &self.arg])
+            .err()
+            .map(|e| e.to_string())
+    }
+}
+
 #[derive(Clone)]
 pub struct NamedThread {
     pub(crate) name: String,
+    pub(crate) tag: Option<String>,
     pub(crate) thread: OwnedThread,
+    pub(crate) yielded_at: Option<Instant>,
+    pub(crate) callback: Option<ThreadCallback>,
+    /// A stable identifier assigned the first time the thread sleeps, used to refer to it in the sleep queue.
+    pub(crate) id: Option<usize>,
+    /// The `usr` captured when the thread was created, restored every time the thread is resumed.
+    pub(crate) usr: Option<ByondValue>,
+    /// If false, the thread uses whatever `usr` was most recently set when it is resumed, instead of the captured one.
+    pub(crate) capture_usr: bool,
+    /// An associative list exposed to the thread as `dm.context`.
+    pub(crate) context: Option<ByondValue>,
 }
 
-use std::collections::VecDeque;
+/// Where a suspended thread was placed by [`push_yielded_thread`].
+pub enum Suspension {
+    /// The thread yielded, and was placed at this index in the yielded threads list.
+    Yield(usize),
+    /// The thread slept, and was placed at the back of the sleep queue with this id.
+    Sleep(usize),
+}
+
+/// A thread removed by [`reap_expired_threads`], along with its index in the yielded threads list
+/// and the error raised by its callback, if any.
+pub type ExpiredThread = (usize, NamedThread, Option<String>);
+
+use std::{
+    collections::VecDeque,
+    ptr,
+    time::{Duration, Instant},
+};
 
 use meowtonin::{ByondResult, ByondValue, ToByond};
 use mlua::{
     prelude::{LuaError, LuaResult, LuaValue},
-    AppDataRef, AppDataRefMut, Lua,
+    AppDataRef, AppDataRefMut, Function, Lua, Table,
 };
 
+use crate::wrappers::wrapped_global_call;
+
 use super::{
-    sleep::take_sleep_flag,
+    sleep::{take_yield_flag, YieldKind},
     util::entrypoint::{get_entrypoint, remove_main_chunk},
 };
 #[derive(Default)]
 pub struct Threads {
     pub yields: Vec<Option<NamedThread>>,
     pub sleeps: VecDeque<NamedThread>,
+    pub next_sleep_id: usize,
 }
 
 fn get_thread_storage(lua: &'_ Lua) -> AppDataRef<'_, Threads> {
@@ -42,17 +101,28 @@ fn get_thread_storage_mut(lua: &'_ Lua) -> AppDataRefMut<'_, Threads> {
         .unwrap()
 }
 
-pub fn push_yielded_thread(lua: &Lua, thread: NamedThread) -> LuaResult<Option<usize>> {
+pub fn push_yielded_thread(lua: &Lua, mut thread: NamedThread) -> LuaResult<Suspension> {
     let mut storage = get_thread_storage_mut(lua);
-    if take_sleep_flag() {
+    if take_yield_flag() == Some(YieldKind::Sleep) {
+        thread.yielded_at = None;
+        let id = match thread.id {
+            Some(id) => id,
+            None => {
+                storage.next_sleep_id += 1;
+                storage.next_sleep_id
+            }
+        };
+        thread.id = Some(id);
         storage.sleeps.push_back(thread);
-        Ok(None)
+        Ok(Suspension::Sleep(id))
     } else if let Some(index) = storage.yields.iter().position(Option::is_none) {
+        thread.yielded_at = Some(Instant::now());
         storage.yields[index].replace(thread);
-        Ok(Some(index))
+        Ok(Suspension::Yield(index))
     } else {
+        thread.yielded_at = Some(Instant::now());
         storage.yields.push(Some(thread));
-        Ok(Some(storage.yields.len() - 1))
+        Ok(Suspension::Yield(storage.yields.len() - 1))
     }
 }
 
@@ -81,6 +151,31 @@ pub fn get_yielded_thread(lua: &Lua, index: usize) -> LuaResult<NamedThread> {
     }
 }
 
+/// Where a suspended thread is stored, so that it can be inspected before it is taken.
+pub enum ThreadLocation<'a> {
+    FrontSleeping,
+    FrontTaggedSleeping(&'a str),
+    SleepingId(usize),
+    Yielded(usize),
+}
+
+/// Returns the name of the suspended thread at `location`, if there is one.
+pub fn peek_thread_name(lua: &Lua, location: ThreadLocation) -> Option<String> {
+    let storage = get_thread_storage(lua);
+    match location {
+        ThreadLocation::FrontSleeping => storage.sleeps.front(),
+        ThreadLocation::FrontTaggedSleeping(tag) => storage
+            .sleeps
+            .iter()
+            .find(|thread| thread.tag.as_deref() == Some(tag)),
+        ThreadLocation::SleepingId(id) => {
+            storage.sleeps.iter().find(|thread| thread.id == Some(id))
+        }
+        ThreadLocation::Yielded(index) => storage.yields.get(index).and_then(Option::as_ref),
+    }
+    .map(|thread| thread.name.clone())
+}
+
 pub fn pop_front_sleeping_thread(lua: &Lua) -> LuaResult<NamedThread> {
     let mut storage = get_thread_storage_mut(lua);
     storage
@@ -89,6 +184,49 @@ pub fn pop_front_sleeping_thread(lua: &Lua) -> LuaResult<NamedThread> {
         .ok_or_else(|| LuaError::external("Sleep queue is empty"))
 }
 
+pub fn pop_front_tagged_sleeping_thread(lua: &Lua, tag: &str) -> LuaResult<NamedThread> {
+    let mut storage = get_thread_storage_mut(lua);
+    storage
+        .sleeps
+        .iter()
+        .position(|thread| thread.tag.as_deref() == Some(tag))
+        .and_then(|index| storage.sleeps.remove(index))
+        .ok_or_else(|| LuaError::external(format!("No sleeping threads with tag \"{tag}\"")))
+}
+
+fn sleeping_thread_position(storage: &Threads, id: usize) -> LuaResult<usize> {
+    storage
+        .sleeps
+        .iter()
+        .position(|thread| thread.id == Some(id))
+        .ok_or_else(|| LuaError::external(format!("No sleeping thread with id {id}")))
+}
+
+pub fn remove_sleeping_thread_by_id(lua: &Lua, id: usize) -> LuaResult<NamedThread> {
+    let mut storage = get_thread_storage_mut(lua);
+    let index = sleeping_thread_position(&storage, id)?;
+    Ok(storage.sleeps.remove(index).unwrap())
+}
+
+/// Moves the sleeping thread with the specified id to the front of the sleep queue,
+/// so that it is the next thread to be run by `awaken`.
+pub fn prioritize_sleeping_thread(lua: &Lua, id: usize) -> LuaResult<()> {
+    let mut storage = get_thread_storage_mut(lua);
+    let index = sleeping_thread_position(&storage, id)?;
+    let thread = storage.sleeps.remove(index).unwrap();
+    storage.sleeps.push_front(thread);
+    Ok(())
+}
+
+/// Returns the ids of every thread currently in the sleep queue, in queue order.
+pub fn sleeping_thread_ids(lua: &Lua) -> Vec<usize> {
+    get_thread_storage(lua)
+        .sleeps
+        .iter()
+        .filter_map(|thread| thread.id)
+        .collect()
+}
+
 pub fn remove_sleeping_thread(lua: &Lua, index: usize) -> LuaResult<NamedThread> {
     let mut storage = get_thread_storage_mut(lua);
     storage
@@ -97,26 +235,138 @@ pub fn remove_sleeping_thread(lua: &Lua, index: usize) -> LuaResult<NamedThread>
         .ok_or_else(|| LuaError::external("Index out of bounds".to_string().as_str()))
 }
 
+/// Removes every sleeping or yielded thread with the specified tag, returning the removed threads.
+pub fn remove_tagged_threads(lua: &Lua, tag: &str) -> Vec<NamedThread> {
+    let mut storage = get_thread_storage_mut(lua);
+    let has_tag = |thread: &NamedThread| thread.tag.as_deref() == Some(tag);
+    let (removed_sleeps, kept_sleeps) = storage.sleeps.drain(..).partition(has_tag);
+    storage.sleeps = kept_sleeps;
+    removed_sleeps
+        .into_iter()
+        .chain(
+            storage
+                .yields
+                .iter_mut()
+                .filter(|opt| opt.as_ref().is_some_and(has_tag))
+                .filter_map(Option::take)
+                .collect::<Vec<_>>(),
+        )
+        .collect()
+}
+
+/// Removes every yielded thread that has been yielded for longer than the state's yield timeout, if one is set.
+///
+/// Returns the removed threads along with their indices in the yielded threads list,
+/// and any error raised by their callbacks.
+pub fn reap_expired_threads(lua: &Lua) -> LuaResult<Vec<ExpiredThread>> {
+    // The timeout is validated when it is set, so converting it here cannot fail.
+    let Some(timeout) = lua
+        .named_registry_value::<Option<f32>>("yield_timeout")?
+        .map(Duration::from_secs_f32)
+    else {
+        return Ok(vec![]);
+    };
+    let expired = get_thread_storage_mut(lua)
+        .yields
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, opt)| {
+            opt.as_ref()
+                .and_then(|thread| thread.yielded_at)
+                .is_some_and(|yielded_at| yielded_at.elapsed() > timeout)
+        })
+        .filter_map(|(i, opt)| opt.take().map(|thread| (i, thread)))
+        .collect::<Vec<_>>();
+    Ok(expired
+        .into_iter()
+        .map(|(index, thread)| {
+            let callback_error = discard_thread(lua, &thread, "expired");
+            (index, thread, callback_error)
+        })
+        .collect())
+}
+
+/// Cleans up after a thread that is removed without finishing.
+///
+/// If the thread has a callback, it is called with a result list whose "status" is `status`, along with the thread's "name".
+/// Returns the error raised by the callback, if any.
+pub fn discard_thread(lua: &Lua, thread: &NamedThread, status: &str) -> Option<String> {
+    remove_main_chunk(&get_entrypoint(lua, &thread.thread).unwrap_or(ptr::null()));
+    let callback = thread.callback.as_ref()?;
+    (|| {
+        vec![
+            ("status", status.to_byond()?),
+            ("name", thread.name.to_byond()?),
+        ]
+        .to_byond()
+    })()
+    .map_or_else(|e| Some(e.to_string()), |result| callback.invoke(&result))
+}
+
+/// Registry key of a weak-keyed table associating the environments of tagged chunks with their tags.
+const CHUNK_TAGS: &str = "chunk_tags";
+
+/// Creates the environment for a chunk loaded with a tag. Every function the chunk defines shares this environment,
+/// so that those functions can be run under the chunk's tag, even if another chunk is loaded with the same name.
+///
+/// Reads and writes of globals go straight through to the state's globals.
+pub fn create_tagged_environment<'lua>(lua: &'lua Lua, tag: &str) -> LuaResult<Table<'lua>> {
+    let chunk_tags = match lua.named_registry_value::<Option<Table>>(CHUNK_TAGS)? {
+        Some(chunk_tags) => chunk_tags,
+        None => {
+            let chunk_tags = lua.create_table()?;
+            chunk_tags.set_metatable(Some(lua.create_table_from([("__mode", "k")])?));
+            lua.set_named_registry_value(CHUNK_TAGS, &chunk_tags)?;
+            chunk_tags
+        }
+    };
+    let globals = lua.globals();
+    let environment = lua.create_table()?;
+    environment.set_metatable(Some(
+        lua.create_table_from([("__index", globals.clone()), ("__newindex", globals)])?,
+    ));
+    chunk_tags.raw_set(&environment, tag)?;
+    Ok(environment)
+}
+
+/// Returns the tag of the chunk that defined `func`, if that chunk was loaded with a tag.
+pub fn get_function_tag(lua: &Lua, func: &Function) -> LuaResult<Option<String>> {
+    match (
+        func.environment(),
+        lua.named_registry_value::<Option<Table>>(CHUNK_TAGS)?,
+    ) {
+        (Some(environment), Some(chunk_tags)) => chunk_tags.raw_get(environment),
+        _ => Ok(None),
+    }
+}
+
 pub type ThreadList = Vec<(&'static str, Vec<Vec<(&'static str, ByondValue)>>)>;
 
-pub fn list_threads(lua: &Lua) -> ByondResult<ThreadList> {
+pub fn thread_info(
+    index: usize,
+    thread: &NamedThread,
+) -> ByondResult<Vec<(&'static str, ByondValue)>> {
+    Ok(vec![
+        ("index", index.to_byond()?),
+        ("name", thread.name.to_byond()?),
+        ("tag", thread.tag.to_byond()?),
+        ("id", thread.id.to_byond()?),
+    ])
+}
+
+pub fn list_threads(lua: &Lua, tag: Option<String>) -> ByondResult<ThreadList> {
     let storage = get_thread_storage(lua);
+    let matches_tag = |thread: &NamedThread| tag.is_none() || thread.tag == tag;
     Ok(vec![
         (
             "yields",
             storage
                 .yields
                 .iter()
-                .map(|opt| opt.as_ref().map(|NamedThread { name, thread: _ }| name))
                 .enumerate()
-                .filter_map(|(i, opt)| {
-                    opt.map(|name| {
-                        Ok(vec![
-                            ("index", i.to_byond()?),
-                            ("name", name.to_byond().unwrap()),
-                        ])
-                    })
-                })
+                .filter_map(|(i, opt)| opt.as_ref().map(|thread| (i, thread)))
+                .filter(|(_, thread)| matches_tag(thread))
+                .map(|(i, thread)| thread_info(i, thread))
                 .collect::<ByondResult<_>>()?,
         ),
         (
@@ -124,14 +374,9 @@ pub fn list_threads(lua: &Lua) -> ByondResult<ThreadList> {
             storage
                 .sleeps
                 .iter()
-                .map(|NamedThread { name, thread: _ }| name)
                 .enumerate()
-                .map(|(i, name)| {
-                    Ok(vec![
-                        ("index", i.to_byond()?),
-                        ("name", name.to_byond().unwrap()),
-                    ])
-                })
+                .filter(|(_, thread)| matches_tag(thread))
+                .map(|(i, thread)| thread_info(i, thread))
                 .collect::<ByondResult<_>>()?,
         ),
     ])
@@ -16,9 +16,15 @@ pub fn set_usr(new_usr: ByondValue) {
     usr.replace(new_usr);
 }
 
-#[map_statics(mut USR, mut USR_STACK)]
-pub fn push_usr() {
-    usr_stack.push(usr.take())
+/// Takes the `usr` most recently passed into [`set_usr`], if it has not already been taken.
+#[map_statics(mut USR)]
+pub fn take_usr() -> Option<ByondValue> {
+    usr.take()
+}
+
+#[map_statics(mut USR_STACK)]
+pub fn push_usr(new_usr: Option<ByondValue>) {
+    usr_stack.push(new_usr)
 }
 
 #[map_statics(mut USR_STACK)]
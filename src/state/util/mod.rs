@@ -1,8 +1,13 @@
-use mlua::{prelude::LuaResult, Lua};
+use mlua::{prelude::LuaResult, Function, Lua, Table};
 
-use self::{entrypoint::get_entrypoint_function, traceback::dm_traceback};
+use crate::value::BUFFER_TOSTRING;
+
+use self::{
+    entrypoint::get_entrypoint_function, resume_error::resume_error, traceback::dm_traceback,
+};
 
 pub mod entrypoint;
+pub mod resume_error;
 pub mod traceback;
 
 pub fn prepare_registry_functions(lua: &Lua) -> LuaResult<()> {
@@ -11,5 +16,14 @@ pub fn prepare_registry_functions(lua: &Lua) -> LuaResult<()> {
     }?)?;
     lua.set_named_registry_value("get_entrypoint", unsafe {
         lua.create_c_function(get_entrypoint_function)
-    }?)
+    }?)?;
+    lua.set_named_registry_value("resume_error", unsafe {
+        lua.create_c_function(resume_error)
+    }?)?;
+    lua.set_named_registry_value(
+        BUFFER_TOSTRING,
+        lua.globals()
+            .raw_get::<_, Table>("buffer")?
+            .raw_get::<_, Function>("tostring")?,
+    )
 }
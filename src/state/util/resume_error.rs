@@ -0,0 +1,33 @@
+use std::os::raw::c_int;
+
+use mlua::{
+    ffi::{
+        lua_checkstack, lua_gettop, lua_pushboolean, lua_resumeerror, lua_tothread, lua_xmove,
+        LUA_OK, LUA_YIELD,
+    },
+    lua_State,
+};
+
+/// Resumes the thread passed as the first argument, raising the second argument as an error
+/// from the point where the thread yielded.
+///
+/// Returns values the same way `coroutine.resume` does - `true` followed by any values yielded or returned by the thread,
+/// or `false` followed by the error that killed the thread.
+pub unsafe extern "C-unwind" fn resume_error(lua: *mut lua_State) -> c_int {
+    let thread = lua_tothread(lua, 1);
+    lua_xmove(lua, thread, 1);
+    match lua_resumeerror(thread, lua) {
+        LUA_OK | LUA_YIELD => {
+            let n_results = lua_gettop(thread);
+            lua_checkstack(lua, n_results + 1);
+            lua_pushboolean(lua, 1);
+            lua_xmove(thread, lua, n_results);
+            n_results + 1
+        }
+        _ => {
+            lua_pushboolean(lua, 0);
+            lua_xmove(thread, lua, 1);
+            2
+        }
+    }
+}
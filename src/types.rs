@@ -213,6 +213,18 @@ pub const DATUM_TYPES: &[u8] = &[
     ValueTag::Datum,
 ];
 
+/// All types that are considered atoms, and so have map coordinates
+pub const ATOM_TYPES: &[u8] = &[ValueTag::Turf, ValueTag::Obj, ValueTag::Mob, ValueTag::Area];
+
+/// All types of typepath values
+pub const TYPEPATH_TYPES: &[u8] = &[
+    ValueTag::MobTypepath,
+    ValueTag::ObjTypepath,
+    ValueTag::TurfTypepath,
+    ValueTag::AreaTypepath,
+    ValueTag::DatumTypepath,
+];
+
 /// Types that can be indexed by arbitrary strings
 pub const STRING_INDEXABLE_TYPES: &[u8] = concat_slices!([u8]:
     VARS_TYPES,
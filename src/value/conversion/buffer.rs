@@ -0,0 +1,57 @@
+use std::ffi::CString;
+
+use meowtonin::ByondValue;
+use mlua::{
+    prelude::{LuaError, LuaResult, LuaValue},
+    AnyUserData, Function, Lua,
+};
+
+use crate::traits::AsPrintedExternalResult;
+
+use super::super::{ByondObject, Value};
+
+/// Registry key of the original `buffer.tostring`, which is the only way to read a buffer's contents without unsafe code.
+pub const BUFFER_TOSTRING: &str = "buffer_tostring";
+
+/// Converts a buffer to a DM string containing the same bytes.
+pub fn convert_buffer(lua: &Lua, buffer: LuaValue) -> LuaResult<Value> {
+    lua.named_registry_value::<Function>(BUFFER_TOSTRING)?
+        .call::<_, mlua::String>(buffer)
+        .and_then(|string| CString::new(string.as_bytes()).map_err(LuaError::external))
+        .map(ByondValue::new_string)
+        .map(Value)
+}
+
+/// Creates a buffer from a string, or from a DM list of numbers between 0 and 255.
+pub fn create_buffer_from_dm<'lua>(
+    lua: &'lua Lua,
+    value: LuaValue<'lua>,
+) -> LuaResult<AnyUserData<'lua>> {
+    match value {
+        LuaValue::String(string) => lua.create_buffer(string.as_bytes()),
+        LuaValue::UserData(ud) if ud.is::<ByondObject>() => {
+            let list = ud.borrow::<ByondObject>()?.0.clone();
+            if !list.is_list() {
+                return Err(LuaError::external("expected a string or a list of bytes"));
+            }
+            let bytes = list
+                .read_list()
+                .into_printed_external()?
+                .into_iter()
+                .map(|element| match element.get_number() {
+                    Ok(byte) if byte.fract() == 0.0 && (0.0..=255.0).contains(&byte) => {
+                        Ok(byte as u8)
+                    }
+                    _ => Err(LuaError::external(
+                        "lists converted to buffers can only contain integers between 0 and 255",
+                    )),
+                })
+                .collect::<LuaResult<Vec<u8>>>()?;
+            lua.create_buffer(bytes)
+        }
+        other => Err(LuaError::external(format!(
+            "expected a string or a list of bytes, got {}",
+            other.type_name()
+        ))),
+    }
+}
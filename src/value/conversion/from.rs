@@ -1,4 +1,9 @@
-use super::super::{ByondObject, Value};
+use super::{
+    super::{as_list_element, ByondObject, Value},
+    buffer::convert_buffer,
+    limits::{count_converted_element, ConversionDepthGuard},
+    ordered::ordered_pairs,
+};
 
 use mlua::{
     prelude::{FromLua, Lua, LuaError, LuaResult, LuaValue},
@@ -56,6 +61,29 @@ impl ToByond for ConversionVariant {
     }
 }
 
+/// If the value is a table or userdata with a `__todm` metamethod, returns the result of calling that metamethod with the value.
+/// Otherwise, returns the value unchanged.
+///
+/// The hook is only applied once, so a `__todm` metamethod can return its own argument to be converted normally.
+pub fn apply_todm_hook(value: LuaValue) -> LuaResult<LuaValue> {
+    let hook = match &value {
+        LuaValue::Table(t) => t
+            .get_metatable()
+            .map(|metatable| metatable.raw_get::<_, LuaValue>("__todm"))
+            .transpose()?,
+        LuaValue::UserData(ud) if !ud.is::<ByondObject>() => ud
+            .get_metatable()
+            .ok()
+            .map(|metatable| metatable.get::<LuaValue>("__todm"))
+            .transpose()?,
+        _ => None,
+    };
+    match hook {
+        Some(LuaValue::Function(f)) => f.call(value),
+        _ => Ok(value),
+    }
+}
+
 pub fn convert_from_table(lua: &Lua, table: Table) -> LuaResult<Value> {
     convert_from_table_impl(lua, table, false, &mut HashMap::new()).map(|(v, _)| v)
 }
@@ -70,6 +98,118 @@ pub fn safe_convert_from_table(lua: &Lua, table: Table) -> LuaResult<(Value, Con
     convert_from_table_impl(lua, table, true, &mut HashMap::new())
 }
 
+/// Converts an array of `{key, value}` tables, as created by `list.to_table` in pairs mode, to a list.
+///
+/// If `deep` is true, tables inside the entries are also converted as arrays of pairs.
+pub fn convert_from_pairs(lua: &Lua, (pairs, deep): (Table, Option<bool>)) -> LuaResult<Value> {
+    convert_from_pairs_impl(lua, pairs, deep.unwrap_or(false), &mut HashMap::new())
+}
+
+fn convert_pair_element(
+    lua: &Lua,
+    element: LuaValue,
+    deep: bool,
+    visited: &mut HashMap<*const c_void, Value>,
+) -> LuaResult<ByondValue> {
+    match element {
+        LuaValue::Table(t) if deep => convert_from_pairs_impl(lua, t, deep, visited),
+        anything_else => Value::from_lua(anything_else, lua),
+    }
+    .map(|Value(v)| v)
+}
+
+fn convert_from_pairs_impl(
+    lua: &Lua,
+    pairs: Table,
+    deep: bool,
+    visited: &mut HashMap<*const c_void, Value>,
+) -> LuaResult<Value> {
+    let ptr = pairs.to_pointer();
+    if let Some(v) = visited.get(&ptr) {
+        return Ok(v.clone());
+    }
+    let _depth_guard = ConversionDepthGuard::enter()?;
+    let mut list = ByondValue::new_list().into_printed_external()?;
+    visited.insert(ptr, Value(list.clone()));
+    let entries = pairs
+        .sequence_values::<Table>()
+        .collect::<LuaResult<Vec<_>>>()?;
+    let converted = entries
+        .into_iter()
+        .map(|entry| {
+            count_converted_element(lua)?;
+            let key = convert_pair_element(lua, entry.raw_get(1)?, deep, visited)?;
+            let value = convert_pair_element(lua, entry.raw_get(2)?, deep, visited)?;
+            if !value.is_null() && (key.is_null() || key.is_number()) {
+                return Err(LuaError::external(format!(
+                    "cannot associate a value with {}",
+                    if key.is_null() { "nil" } else { "a number" }
+                )));
+            }
+            Ok((key, value))
+        })
+        .collect::<LuaResult<Vec<_>>>()?;
+    // As in DM, an assoc value is written to the first entry with its key,
+    // so the values of later entries that repeat a key end up on that first entry.
+    converted
+        .iter()
+        .map(|(key, _)| as_list_element(key))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|elements| list.call::<_, _, _, ByondValue>("Add", elements))
+        .and_then(|_| {
+            converted
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .try_for_each(|(key, value)| list.write_list_index(key, value))
+        })
+        .into_printed_external()?;
+    Ok(Value(list))
+}
+
+/// Converts a key or value of a table being converted by [`convert_from_table_impl`].
+/// In safe mode, values that cannot be converted are represented by a string describing them,
+/// and conversion errors are passed to `on_error` to produce the entry's replacement.
+fn convert_table_entry(
+    lua: &Lua,
+    entry: LuaValue,
+    safe: bool,
+    visited: &mut HashMap<*const c_void, Value>,
+    on_error: impl FnOnce(LuaError) -> (Value, ConversionVariant),
+) -> LuaResult<(Value, ConversionVariant)> {
+    // The entry is hooked here, rather than by `convert_value`,
+    // so that a failing hook only fails its own entry in safe mode.
+    let entry = match apply_todm_hook(entry) {
+        Ok(entry) => entry,
+        Err(e) if safe => return Ok(on_error(e)),
+        Err(e) => return Err(e),
+    };
+    Ok(match entry {
+        LuaValue::Table(t) => convert_from_table_impl(lua, t, safe, visited)?,
+        LuaValue::Function(ref f) if safe => (
+            Value::from(format!(
+                "{}: {:p}",
+                f.info().name.unwrap_or(String::from("anonymous function")),
+                entry.to_pointer()
+            )),
+            ConversionVariant::Function,
+        ),
+        LuaValue::Thread(_) if safe => (
+            Value::from(format!("{:p}", entry.to_pointer())),
+            ConversionVariant::Thread,
+        ),
+        LuaValue::UserData(ref ud) if !ud.is::<ByondObject>() && !entry.is_buffer() && safe => (
+            Value::from(format!("{:p}", entry.to_pointer())),
+            ConversionVariant::Userdata,
+        ),
+        LuaValue::Error(e) if safe => (Value::from(e.to_string()), ConversionVariant::ErrorAsValue),
+        anything_else => match convert_value(anything_else, lua) {
+            Ok(v) => (v, ConversionVariant::None),
+            Err(e) if safe => on_error(e),
+            Err(e) => return Err(e).into_printed_external(),
+        },
+    })
+}
+
 pub fn convert_from_table_impl(
     lua: &Lua,
     table: Table,
@@ -84,13 +224,18 @@ pub fn convert_from_table_impl(
             .into_printed_external()
             .map(Value)
             .and_then(|Value(mut list)| {
+                let _depth_guard = ConversionDepthGuard::enter()?;
                 visited.insert(ptr, Value(list.clone()));
                 let mut variants: Vec<Option<ConversionKVP>> = vec![];
                 let mut key_error_messages: HashSet<String> = HashSet::new();
-                let mut pairs = table
-                    .pairs::<LuaValue, LuaValue>()
-                    .map(Result::unwrap)
-                    .collect::<Vec<_>>();
+                // Ordered tables keep their insertion order through the sort below, as it is stable.
+                let mut pairs = match ordered_pairs(lua, &table)? {
+                    Some(pairs) => pairs,
+                    None => table
+                        .pairs::<LuaValue, LuaValue>()
+                        .map(Result::unwrap)
+                        .collect::<Vec<_>>(),
+                };
                 // Ensure integers (and floats that are exactly equal to integers) come before floats, which come before everything else.
                 // BYOND does not allow floats as list indices, and writing to a list by integer index after writing to it by assoc index
                 // risks clobbering an assoc entry.
@@ -134,73 +279,32 @@ pub fn convert_from_table_impl(
                 pairs
                     .into_iter()
                     .try_fold(Value(list), |Value(mut list), (key, value)| {
-                        let (key, key_variant) = match key {
-                            LuaValue::Table(t) => convert_from_table_impl(lua, t, safe, visited)?,
-                            LuaValue::Function(ref f) if safe => (
-                                Value::from(format!(
-                                    "{}: {:p}",
-                                    f.info().name.unwrap_or(String::from("anonymous function")),
-                                    key.to_pointer()
-                                )),
-                                ConversionVariant::Function,
-                            ),
-                            LuaValue::Thread(_) if safe => (
-                                Value::from(format!("{:p}", key.to_pointer())),
-                                ConversionVariant::Thread,
-                            ),
-                            LuaValue::UserData(ref ud) if !ud.is::<ByondObject>() && safe => (
-                                Value::from(format!("{:p}", key.to_pointer())),
-                                ConversionVariant::Userdata,
-                            ),
-                            LuaValue::Error(e) if safe => {
-                                (Value::from(e.to_string()), ConversionVariant::ErrorAsValue)
+                        count_converted_element(lua)?;
+                        let mut key_conversion_error = |e: LuaError| {
+                            let mut err_string = e.to_string();
+                            while key_error_messages.contains(&err_string) {
+                                err_string.push(' ');
                             }
-                            anything_else => match Value::from_lua(anything_else, lua) {
-                                Ok(v) => (v, ConversionVariant::None),
-                                Err(e) if safe => {
-                                    let mut err_string = e.to_string();
-                                    while key_error_messages.contains(&err_string) {
-                                        err_string.push(' ');
-                                    }
-                                    key_error_messages.insert(err_string.clone());
-                                    (
-                                        Value(ByondValue::new_string(err_string)),
-                                        ConversionVariant::ConversionError,
-                                    )
-                                }
-                                Err(e) => return Err(e).into_printed_external(),
-                            },
+                            key_error_messages.insert(err_string.clone());
+                            (
+                                Value(ByondValue::new_string(err_string)),
+                                ConversionVariant::ConversionError,
+                            )
                         };
-                        let (value, value_variant) = match value {
-                            LuaValue::Table(t) => convert_from_table_impl(lua, t, safe, visited)?,
-                            LuaValue::Function(ref f) if safe => (
-                                Value::from(format!(
-                                    "{}: {:p}",
-                                    f.info().name.unwrap_or(String::from("anonymous function")),
-                                    value.to_pointer()
-                                )),
-                                ConversionVariant::Function,
-                            ),
-                            LuaValue::Thread(_) if safe => (
-                                Value::from(format!("{:p}", value.to_pointer())),
-                                ConversionVariant::Thread,
-                            ),
-                            LuaValue::UserData(ref ud) if !ud.is::<ByondObject>() && safe => (
-                                Value::from(format!("{:p}", value.to_pointer())),
-                                ConversionVariant::Userdata,
-                            ),
-                            LuaValue::Error(e) if safe => {
-                                (Value::from(e.to_string()), ConversionVariant::ErrorAsValue)
-                            }
-                            anything_else => match Value::from_lua(anything_else, lua) {
-                                Ok(v) => (v, ConversionVariant::None),
-                                Err(e) if safe => (
+                        let (key, key_variant) = convert_table_entry(
+                            lua,
+                            key,
+                            safe,
+                            visited,
+                            &mut key_conversion_error,
+                        )?;
+                        let (value, value_variant) =
+                            convert_table_entry(lua, value, safe, visited, |e| {
+                                (
                                     Value(ByondValue::new_string(e.to_string())),
                                     ConversionVariant::ConversionError,
-                                ),
-                                Err(e) => return Err(e).into_printed_external(),
-                            },
-                        };
+                                )
+                            })?;
                         list.write_list_index(key.clone(), value)
                             .or_else(|e| {
                                 if safe {
@@ -255,36 +359,42 @@ pub fn convert_from_table_impl(
 
 impl<'lua> FromLua<'lua> for Value {
     fn from_lua(value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
-        match value {
-            LuaValue::Nil => Ok(Self(ByondValue::NULL)),
-            LuaValue::Boolean(b) => Ok(Self(b.to_byond().unwrap())),
-            LuaValue::Integer(n) => n.to_byond().map(Self).into_printed_external(),
-            LuaValue::Number(n) => (n as f32).to_byond().map(Self).into_printed_external(),
-            LuaValue::String(s) => CString::new(s.as_bytes())
-                .map(ByondValue::new_string)
-                .map(Self)
-                .map_err(LuaError::external),
-            LuaValue::UserData(u) if u.is::<ByondObject>() => {
-                u.borrow::<ByondObject>().map(|r| Value(r.0.clone()))
-            }
-            LuaValue::UserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "destructed userdata",
-                to: "BYOND value",
-                message: Some(String::from(
-                    "The associated object has been cleared from lua memory",
-                )),
-            }),
-            LuaValue::Table(t) => convert_from_table(lua, t),
-            LuaValue::Vector(v) => [v.x(), v.y(), v.z()]
-                .to_byond()
-                .map(Self)
-                .into_printed_external(),
-            LuaValue::Error(e) => Ok(Self(e.to_string().to_byond().unwrap())),
-            anything_else => Err(LuaError::FromLuaConversionError {
-                from: anything_else.type_name(),
-                to: "BYOND value",
-                message: Some(String::from("Unsupported value type")),
-            }),
+        convert_value(apply_todm_hook(value)?, lua)
+    }
+}
+
+/// Converts a value whose `__todm` hook, if any, has already been applied.
+pub fn convert_value(value: LuaValue, lua: &Lua) -> LuaResult<Value> {
+    match value {
+        buffer if buffer.is_buffer() => convert_buffer(lua, buffer),
+        LuaValue::Nil => Ok(Value(ByondValue::NULL)),
+        LuaValue::Boolean(b) => Ok(Value(b.to_byond().unwrap())),
+        LuaValue::Integer(n) => n.to_byond().map(Value).into_printed_external(),
+        LuaValue::Number(n) => (n as f32).to_byond().map(Value).into_printed_external(),
+        LuaValue::String(s) => CString::new(s.as_bytes())
+            .map(ByondValue::new_string)
+            .map(Value)
+            .map_err(LuaError::external),
+        LuaValue::UserData(u) if u.is::<ByondObject>() => {
+            u.borrow::<ByondObject>().map(|r| Value(r.0.clone()))
         }
+        LuaValue::UserData(_) => Err(LuaError::FromLuaConversionError {
+            from: "destructed userdata",
+            to: "BYOND value",
+            message: Some(String::from(
+                "The associated object has been cleared from lua memory",
+            )),
+        }),
+        LuaValue::Table(t) => convert_from_table(lua, t),
+        LuaValue::Vector(v) => [v.x(), v.y(), v.z()]
+            .to_byond()
+            .map(Value)
+            .into_printed_external(),
+        LuaValue::Error(e) => Ok(Value(e.to_string().to_byond().unwrap())),
+        anything_else => Err(LuaError::FromLuaConversionError {
+            from: anything_else.type_name(),
+            to: "BYOND value",
+            message: Some(String::from("Unsupported value type")),
+        }),
     }
 }
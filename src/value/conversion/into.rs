@@ -1,9 +1,12 @@
-use super::super::Value;
+use super::{
+    super::Value,
+    limits::{count_converted_element, ConversionDepthGuard},
+};
 use crate::cache::userdata::get_or_create_cached_userdata;
 
 use mlua::{
-    prelude::{IntoLua, LuaResult, LuaValue},
-    Lua, Table,
+    prelude::{FromLua, IntoLua, LuaError, LuaResult, LuaValue},
+    Function, Lua, Table,
 };
 
 use std::{collections::HashMap, ffi::CString};
@@ -12,61 +15,159 @@ use meowtonin::{ByondError, ByondValue, FromByond};
 
 use crate::traits::{AsPrintedExternal, AsPrintedExternalResult};
 
-pub fn convert_to_table(
-    lua: &Lua,
-    (Value(ref list), deep): (Value, Option<bool>),
-) -> LuaResult<Table<'_>> {
-    convert_to_table_impl(lua, list, &mut HashMap::new(), deep.unwrap_or(false))
+/// How the entries of a list are laid out in the table it is converted to.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToTableMode {
+    /// Assoc entries are merged into the same table as plain entries,
+    /// which loses duplicate keys and the positions of assoc entries.
+    #[default]
+    Merged,
+    /// Every entry is converted to a `{key, value}` table, in list order.
+    Pairs,
+}
+
+/// The options accepted by `list.to_table`.
+///
+/// Can be passed in as a boolean, which only sets `deep`, or as a table.
+#[derive(Default)]
+pub struct ToTableOptions<'lua> {
+    pub deep: bool,
+    pub mode: ToTableMode,
+    /// Called with each table created from a list, with its return value used in place of that table.
+    pub fromdm: Option<Function<'lua>>,
+}
+
+impl<'lua> FromLua<'lua> for ToTableOptions<'lua> {
+    fn from_lua(value: LuaValue<'lua>, _: &'lua Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Boolean(deep) => Ok(Self {
+                deep,
+                ..Default::default()
+            }),
+            LuaValue::Table(options) => Ok(Self {
+                deep: options.get::<_, Option<bool>>("deep")?.unwrap_or(false),
+                mode: match options.get::<_, Option<String>>("mode")?.as_deref() {
+                    None | Some("merged") => ToTableMode::Merged,
+                    Some("pairs") => ToTableMode::Pairs,
+                    Some(other) => {
+                        return Err(LuaError::external(format!(
+                            "invalid conversion mode \"{other}\" (expected \"merged\" or \"pairs\")"
+                        )))
+                    }
+                },
+                fromdm: options.get("fromdm")?,
+            }),
+            other => Err(LuaError::FromLuaConversionError {
+                from: other.type_name(),
+                to: "conversion options",
+                message: Some(String::from("expected a boolean or table")),
+            }),
+        }
+    }
+}
+
+pub fn convert_to_table<'lua>(
+    lua: &'lua Lua,
+    (Value(ref list), options): (Value, ToTableOptions<'lua>),
+) -> LuaResult<LuaValue<'lua>> {
+    convert_list(lua, list, &mut HashMap::new(), &options)
 }
 
-fn convert_to_table_impl<'lua>(
+/// Converts a list to a table laid out according to `options.mode`,
+/// then passes the table through the `fromdm` hook, if one is set.
+fn convert_list<'lua>(
     lua: &'lua Lua,
     list: &ByondValue,
-    visited: &mut HashMap<ByondValue, Table<'lua>>,
-    deep: bool,
-) -> LuaResult<Table<'lua>> {
+    visited: &mut HashMap<ByondValue, LuaValue<'lua>>,
+    options: &ToTableOptions<'lua>,
+) -> LuaResult<LuaValue<'lua>> {
     if !list.is_list() {
-        Err(ByondError::NotAList.into_printed_external())
-    } else if let Some(table) = visited.get(list) {
-        Ok(table.clone())
+        return Err(ByondError::NotAList.into_printed_external());
+    } else if let Some(converted) = visited.get(list) {
+        return Ok(converted.clone());
+    }
+    let _depth_guard = ConversionDepthGuard::enter()?;
+    let table = lua.create_table()?;
+    // Lists that contain themselves refer to the table from before it was passed through the hook.
+    visited.insert(list.clone(), LuaValue::Table(table.clone()));
+    match options.mode {
+        ToTableMode::Merged => fill_merged_table(lua, list, &table, visited, options),
+        ToTableMode::Pairs => fill_pairs_table(lua, list, &table, visited, options),
+    }?;
+    let converted = match &options.fromdm {
+        Some(hook) => hook.call(table)?,
+        None => LuaValue::Table(table),
+    };
+    visited.insert(list.clone(), converted.clone());
+    Ok(converted)
+}
+
+fn convert_element<'lua>(
+    lua: &'lua Lua,
+    element: ByondValue,
+    visited: &mut HashMap<ByondValue, LuaValue<'lua>>,
+    options: &ToTableOptions<'lua>,
+) -> LuaResult<LuaValue<'lua>> {
+    if element.is_list() && options.deep {
+        convert_list(lua, &element, visited, options)
     } else {
-        let table = lua.create_table()?;
-        visited.insert(list.clone(), table.clone());
-        let mut index = 1;
-        list.iter()
-            .into_printed_external()
-            .and_then(|mut iter| {
-                iter.try_for_each(|(key, value)| {
-                    let converted_key = if key.is_list() && deep {
-                        LuaValue::Table(convert_to_table_impl(lua, &key, visited, deep)?)
+        Value(element).into_lua(lua)
+    }
+}
+
+fn fill_pairs_table<'lua>(
+    lua: &'lua Lua,
+    list: &ByondValue,
+    table: &Table<'lua>,
+    visited: &mut HashMap<ByondValue, LuaValue<'lua>>,
+    options: &ToTableOptions<'lua>,
+) -> LuaResult<()> {
+    list.read_assoc_list()
+        .into_printed_external()?
+        .into_iter()
+        .try_for_each(|pair| {
+            count_converted_element(lua)?;
+            let entry = lua.create_table_with_capacity(2, 0)?;
+            pair.into_iter().zip(1..).try_for_each(|(element, index)| {
+                entry.raw_set(index, convert_element(lua, element, visited, options)?)
+            })?;
+            table.raw_push(entry)
+        })
+}
+
+fn fill_merged_table<'lua>(
+    lua: &'lua Lua,
+    list: &ByondValue,
+    table: &Table<'lua>,
+    visited: &mut HashMap<ByondValue, LuaValue<'lua>>,
+    options: &ToTableOptions<'lua>,
+) -> LuaResult<()> {
+    let mut index = 1;
+    list.iter().into_printed_external().and_then(|mut iter| {
+        iter.try_for_each(|(key, value)| {
+            count_converted_element(lua)?;
+            let value_is_null = value.is_null();
+            let converted_key = convert_element(lua, key, visited, options)?;
+            let converted_value = convert_element(lua, value, visited, options)?;
+            table
+                .raw_set(
+                    if value_is_null {
+                        LuaValue::Number(index as f64)
                     } else {
-                        Value(key.clone()).into_lua(lua)?
-                    };
-                    let converted_value = if value.is_list() && deep {
-                        LuaValue::Table(convert_to_table_impl(lua, &value, visited, deep)?)
+                        converted_key.clone()
+                    },
+                    if value_is_null {
+                        converted_key
                     } else {
-                        Value(value.clone()).into_lua(lua)?
-                    };
-                    table
-                        .raw_set(
-                            if value.is_null() {
-                                LuaValue::Number(index as f64)
-                            } else {
-                                converted_key.clone()
-                            },
-                            if value.is_null() {
-                                converted_key
-                            } else {
-                                converted_value
-                            },
-                        )
-                        .map(|_| {
-                            index += 1;
-                        })
+                        converted_value
+                    },
+                )
+                .map(|_| {
+                    index += 1;
                 })
-            })
-            .map(|_| table)
-    }
+        })
+    })
 }
 
 impl<'lua> IntoLua<'lua> for Value {
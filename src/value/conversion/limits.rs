@@ -0,0 +1,84 @@
+use std::cell::RefCell;
+
+use dreamluau_proc_macro::map_statics;
+use meowtonin::byond_fn;
+use mlua::{prelude::LuaError, prelude::LuaResult, Lua};
+
+use crate::state::check_execution_limit;
+
+/// The default maximum nesting depth of tables or lists that can be converted, matching the depth limit of `json.decode`.
+pub const DEFAULT_MAX_CONVERSION_DEPTH: usize = 128;
+
+/// How many elements are converted between each check of the execution limit.
+const EXECUTION_LIMIT_CHECK_INTERVAL: usize = 128;
+
+thread_local! {
+    static MAX_CONVERSION_ELEMENTS: RefCell<Option<usize>> = const { RefCell::new(None) };
+    static MAX_CONVERSION_DEPTH: RefCell<Option<usize>> = const { RefCell::new(Some(DEFAULT_MAX_CONVERSION_DEPTH)) };
+    static CONVERSION_DEPTH: RefCell<usize> = const { RefCell::new(0) };
+    static CONVERTED_ELEMENTS: RefCell<usize> = const { RefCell::new(0) };
+}
+
+/// Sets the maximum number of entries that can be converted by a single conversion between a table and a list,
+/// including the entries of any nested tables or lists. Passing null removes the limit.
+#[map_statics(mut MAX_CONVERSION_ELEMENTS)]
+#[byond_fn]
+pub fn set_max_conversion_elements(limit: Option<u32>) {
+    *max_conversion_elements = limit.map(|limit| limit as usize);
+}
+
+/// Sets the maximum nesting depth of tables or lists that can be converted. Passing null removes the limit.
+#[map_statics(mut MAX_CONVERSION_DEPTH)]
+#[byond_fn]
+pub fn set_max_conversion_depth(limit: Option<u32>) {
+    *max_conversion_depth = limit.map(|limit| limit as usize);
+}
+
+/// Tracks the nesting depth of a table or list conversion for as long as it is held.
+///
+/// Entering the outermost conversion resets the count of converted elements.
+pub struct ConversionDepthGuard;
+
+impl ConversionDepthGuard {
+    #[map_statics(mut CONVERSION_DEPTH, mut CONVERTED_ELEMENTS, MAX_CONVERSION_DEPTH)]
+    pub fn enter() -> LuaResult<Self> {
+        if let Some(max) = *max_conversion_depth {
+            if *conversion_depth >= max {
+                return Err(LuaError::external(format!(
+                    "conversion limit reached - tables and lists cannot be nested more than {max} deep"
+                )));
+            }
+        }
+        if *conversion_depth == 0 {
+            *converted_elements = 0;
+        }
+        *conversion_depth += 1;
+        Ok(Self)
+    }
+}
+
+impl Drop for ConversionDepthGuard {
+    #[map_statics(mut CONVERSION_DEPTH)]
+    fn drop(&mut self) {
+        *conversion_depth -= 1;
+    }
+}
+
+/// Counts an entry towards the current conversion's element limit,
+/// periodically checking the execution limit as well.
+#[map_statics(mut CONVERTED_ELEMENTS, MAX_CONVERSION_ELEMENTS)]
+pub fn count_converted_element(lua: &Lua) -> LuaResult<()> {
+    *converted_elements += 1;
+    if let Some(max) = *max_conversion_elements {
+        if *converted_elements > max {
+            return Err(LuaError::external(format!(
+                "conversion limit reached - cannot convert more than {max} entries at once"
+            )));
+        }
+    }
+    if *converted_elements % EXECUTION_LIMIT_CHECK_INTERVAL == 0 {
+        check_execution_limit(lua)
+    } else {
+        Ok(())
+    }
+}
@@ -1,4 +1,15 @@
-pub use from::{convert_from_table, safe_convert_from_table, ConversionVariant};
-pub use into::convert_to_table;
+pub use buffer::{create_buffer_from_dm, BUFFER_TOSTRING};
+pub use from::{
+    apply_todm_hook, convert_from_pairs, convert_from_table, convert_value,
+    safe_convert_from_table, ConversionVariant,
+};
+pub use into::{convert_to_table, ToTableOptions};
+mod buffer;
 mod from;
 mod into;
+mod limits;
+mod ordered;
+mod vector;
+pub use limits::{set_max_conversion_depth, set_max_conversion_elements};
+pub use ordered::{create_ordered_table, ordered_pairs};
+pub use vector::{convert_proc_result, VECTOR_LISTS};
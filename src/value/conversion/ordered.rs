@@ -0,0 +1,174 @@
+use mlua::{
+    prelude::{LuaResult, LuaValue},
+    Lua, Table,
+};
+
+/// Registry key of the metatable shared by all ordered tables.
+const ORDERED_METATABLE: &str = "ordered_table_metatable";
+
+/// Registry key of the weak-keyed table mapping each ordered table to the state tracking the order of its keys.
+///
+/// Each state holds a weak-valued `keys` array of the table's keys in the order they were inserted,
+/// a weak-keyed `positions` table mapping each key to its index in `keys`, the `length` of `keys` including holes,
+/// and the `threshold` length at which `keys` is next compacted.
+const ORDERED_KEYS: &str = "ordered_table_keys";
+
+/// The smallest length at which the keys of an ordered table are compacted.
+const MIN_COMPACTION_THRESHOLD: usize = 8;
+
+fn get_key_orders(lua: &Lua) -> LuaResult<Table<'_>> {
+    match lua.named_registry_value::<Option<Table>>(ORDERED_KEYS)? {
+        Some(orders) => Ok(orders),
+        None => {
+            let orders = create_weak_table(lua, "k")?;
+            lua.set_named_registry_value(ORDERED_KEYS, &orders)?;
+            Ok(orders)
+        }
+    }
+}
+
+fn create_weak_table<'lua>(lua: &'lua Lua, mode: &str) -> LuaResult<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set_metatable(Some(lua.create_table_from([("__mode", mode)])?));
+    Ok(table)
+}
+
+fn create_key_order(lua: &Lua) -> LuaResult<Table<'_>> {
+    let order = lua.create_table()?;
+    order.raw_set("keys", create_weak_table(lua, "v")?)?;
+    order.raw_set("positions", create_weak_table(lua, "k")?)?;
+    order.raw_set("length", 0)?;
+    order.raw_set("threshold", MIN_COMPACTION_THRESHOLD)?;
+    Ok(order)
+}
+
+/// Appends `key` to the insertion order of `table`, removing its previous position if it had one.
+///
+/// Once the keys array grows past its threshold, it is compacted, dropping holes and keys no longer in the table,
+/// so that tables whose keys are repeatedly removed and inserted do not grow without bound.
+fn push_ordered_key<'lua>(
+    table: &Table<'lua>,
+    order: &Table<'lua>,
+    key: LuaValue<'lua>,
+) -> LuaResult<()> {
+    let keys = order.raw_get::<_, Table>("keys")?;
+    let positions = order.raw_get::<_, Table>("positions")?;
+    if let Some(position) = positions.raw_get::<_, Option<usize>>(key.clone())? {
+        keys.raw_set(position, LuaValue::Nil)?;
+    }
+    let length = order.raw_get::<_, usize>("length")? + 1;
+    keys.raw_set(length, key.clone())?;
+    positions.raw_set(key, length)?;
+    order.raw_set("length", length)?;
+    if length >= order.raw_get::<_, usize>("threshold")? {
+        compact_key_order(table, order)?;
+    }
+    Ok(())
+}
+
+fn compact_key_order<'lua>(table: &Table<'lua>, order: &Table<'lua>) -> LuaResult<()> {
+    let keys = order.raw_get::<_, Table>("keys")?;
+    let positions = order.raw_get::<_, Table>("positions")?;
+    let length = order.raw_get::<_, usize>("length")?;
+    let mut live = 0;
+    for index in 1..=length {
+        let key = keys.raw_get::<_, LuaValue>(index)?;
+        keys.raw_set(index, LuaValue::Nil)?;
+        if key.is_nil() {
+            continue;
+        }
+        if table.raw_get::<_, LuaValue>(key.clone())?.is_nil() {
+            positions.raw_set(key, LuaValue::Nil)?;
+            continue;
+        }
+        live += 1;
+        keys.raw_set(live, key.clone())?;
+        positions.raw_set(key, live)?;
+    }
+    order.raw_set("length", live)?;
+    order.raw_set("threshold", (live * 2).max(MIN_COMPACTION_THRESHOLD))
+}
+
+fn get_ordered_metatable(lua: &Lua) -> LuaResult<Table<'_>> {
+    match lua.named_registry_value::<Option<Table>>(ORDERED_METATABLE)? {
+        Some(metatable) => Ok(metatable),
+        None => {
+            let metatable = lua.create_table()?;
+            // `__newindex` is only invoked when assigning to a key not already in the table,
+            // which is exactly when the key needs to be appended to the insertion order.
+            metatable.raw_set(
+                "__newindex",
+                lua.create_function(|lua, (this, key, value): (Table, LuaValue, LuaValue)| {
+                    this.raw_set(key.clone(), value.clone())?;
+                    if !value.is_nil() {
+                        let order = get_key_orders(lua)?.raw_get::<_, Table>(&this)?;
+                        push_ordered_key(&this, &order, key)?;
+                    }
+                    Ok(())
+                })?,
+            )?;
+            metatable.raw_set("__metatable", false)?;
+            lua.set_named_registry_value(ORDERED_METATABLE, &metatable)?;
+            Ok(metatable)
+        }
+    }
+}
+
+/// Creates a table that remembers the order its keys were inserted in,
+/// so that it is converted to a list with its assoc entries in that order.
+///
+/// If `pairs` is passed in, it is treated as an array of `{key, value}` tables to insert, in order.
+pub fn create_ordered_table<'lua>(
+    lua: &'lua Lua,
+    pairs: Option<Table<'lua>>,
+) -> LuaResult<Table<'lua>> {
+    let table = lua.create_table()?;
+    get_key_orders(lua)?.raw_set(&table, create_key_order(lua)?)?;
+    table.set_metatable(Some(get_ordered_metatable(lua)?));
+    if let Some(pairs) = pairs {
+        pairs.sequence_values::<Table>().try_for_each(|entry| {
+            entry.and_then(|entry| {
+                table.set(
+                    entry.raw_get::<_, LuaValue>(1)?,
+                    entry.raw_get::<_, LuaValue>(2)?,
+                )
+            })
+        })?;
+    }
+    Ok(table)
+}
+
+/// If the table was created by [`create_ordered_table`], returns its entries in the order their keys were most recently inserted.
+///
+/// Entries that were inserted with `rawset` come after every other entry, in arbitrary order.
+pub fn ordered_pairs<'lua>(
+    lua: &'lua Lua,
+    table: &Table<'lua>,
+) -> LuaResult<Option<Vec<(LuaValue<'lua>, LuaValue<'lua>)>>> {
+    let Some(orders) = lua.named_registry_value::<Option<Table>>(ORDERED_KEYS)? else {
+        return Ok(None);
+    };
+    let Some(order) = orders.raw_get::<_, Option<Table>>(table)? else {
+        return Ok(None);
+    };
+    let keys = order.raw_get::<_, Table>("keys")?;
+    let positions = order.raw_get::<_, Table>("positions")?;
+    let mut pairs = vec![];
+    for index in 1..=order.raw_get::<_, usize>("length")? {
+        let key = keys.raw_get::<_, LuaValue>(index)?;
+        if key.is_nil() {
+            continue;
+        }
+        let value = table.raw_get::<_, LuaValue>(key.clone())?;
+        if !value.is_nil() {
+            pairs.push((key, value));
+        }
+    }
+    for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+        let (key, value) = pair?;
+        if positions.raw_get::<_, LuaValue>(key.clone())?.is_nil() {
+            pairs.push((key, value));
+        }
+    }
+    Ok(Some(pairs))
+}
@@ -0,0 +1,39 @@
+use meowtonin::ByondValue;
+use mlua::{
+    prelude::{LuaResult, LuaValue},
+    IntoLua, Lua, Vector,
+};
+
+use crate::traits::AsPrintedExternalResult;
+
+use super::super::Value;
+
+/// Registry key of the flag controlling whether lists of 3 numbers returned from procs are converted to vectors.
+pub const VECTOR_LISTS: &str = "vector_lists";
+
+/// Returns the list as a vector if it contains exactly 3 numbers.
+fn list_to_vector(list: &ByondValue) -> LuaResult<Option<Vector>> {
+    // Checked first so that longer lists are not copied just to be rejected.
+    if list.length().into_printed_external()? != 3 {
+        return Ok(None);
+    }
+    let elements = list.read_list().into_printed_external()?;
+    Ok(match elements.as_slice() {
+        [x, y, z] if elements.iter().all(ByondValue::is_number) => Some(Vector::new(
+            x.get_number().unwrap(),
+            y.get_number().unwrap(),
+            z.get_number().unwrap(),
+        )),
+        _ => None,
+    })
+}
+
+/// Converts the return value of a proc call, turning lists of 3 numbers into vectors if the state has opted into it.
+pub fn convert_proc_result(lua: &Lua, Value(value): Value) -> LuaResult<LuaValue<'_>> {
+    if value.is_list() && lua.named_registry_value::<Option<bool>>(VECTOR_LISTS)? == Some(true) {
+        if let Some(vector) = list_to_vector(&value)? {
+            return Ok(LuaValue::Vector(vector));
+        }
+    }
+    Value(value).into_lua(lua)
+}
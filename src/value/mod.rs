@@ -1,10 +1,18 @@
 use meowtonin::{ByondResult, ByondValue, FromByond, ToByond};
-pub use object::ByondObject;
+pub use object::{
+    as_list_element, list_operator, resolve_list_index, text_to_typepath, types_of, ByondObject,
+    LIST_APPEND,
+};
+pub use view::ListView;
 
 mod conversion;
 mod object;
+mod view;
 pub use conversion::{
-    convert_from_table, convert_to_table, safe_convert_from_table, ConversionVariant,
+    apply_todm_hook, convert_from_pairs, convert_from_table, convert_proc_result, convert_to_table,
+    convert_value, create_buffer_from_dm, create_ordered_table, ordered_pairs,
+    safe_convert_from_table, set_max_conversion_depth, set_max_conversion_elements,
+    ConversionVariant, ToTableOptions, BUFFER_TOSTRING, VECTOR_LISTS,
 };
 
 #[derive(Clone, Default, PartialEq, Eq, Hash)]
@@ -1,5 +1,10 @@
-use meowtonin::ByondValue;
-use mlua::prelude::{LuaError, LuaResult};
+use meowtonin::{ByondResult, ByondValue, ToByond};
+use mlua::{
+    prelude::{LuaError, LuaResult},
+    Lua,
+};
+
+use super::Value;
 
 use crate::{
     traits::AsPrintedExternalResult,
@@ -7,8 +12,68 @@ use crate::{
         can_index_at_all, can_index_by_anything, can_index_by_number, can_index_by_string,
         is_valid_proc_index_for_value, is_valid_var_index_for_value, type_name_for_obj,
     },
+    wrappers::wrapped_read_list_index,
 };
 
+/// Registry key of the flag controlling whether writing to the index just past the end of a list appends to the list.
+pub const LIST_APPEND: &str = "list_append";
+
+/// Resolves negative numeric indices into lists relative to the end of the list, so that `-1` refers to the last element.
+pub fn resolve_list_index(object: &ByondValue, index: ByondValue) -> LuaResult<ByondValue> {
+    if can_index_by_number(object) && index.is_number() {
+        let number = index.get_number().into_printed_external()?;
+        if number < 0.0 {
+            let length = object.length().into_printed_external()?;
+            return Ok(ByondValue::new_num(length as f32 + 1.0 + number));
+        }
+    }
+    Ok(index)
+}
+
+/// Checks that a numeric `index` is a whole number within the bounds of `list`.
+pub fn check_list_bounds(list: &ByondValue, index: &ByondValue) -> LuaResult<()> {
+    if !index.is_number() {
+        return Ok(());
+    }
+    let number = index.get_number().into_printed_external()?;
+    let length = list.length().into_printed_external()?;
+    if number.fract() != 0.0 || number < 1.0 || number > length as f32 {
+        return Err(LuaError::external(format!(
+            "list index {number} is out of bounds for a list of length {length}"
+        )));
+    }
+    Ok(())
+}
+
+/// Reads `index` of `list` the same way indexing the list from lua does,
+/// resolving negative indices and checking the index before reading it.
+pub fn read_list_index(list: &ByondValue, index: ByondValue) -> LuaResult<Value> {
+    let index = resolve_list_index(list, index)?;
+    validate_index(list, &index)?;
+    check_list_bounds(list, &index)?;
+    wrapped_read_list_index(list, &index).into_printed_external()
+}
+
+/// Returns whether writing `index` of `list` should append to it instead,
+/// which is the case when the state has opted into it and `index` is one past the end of the list.
+pub fn appends_on_write(lua: &Lua, list: &ByondValue, index: &ByondValue) -> LuaResult<bool> {
+    if !index.is_number() || lua.named_registry_value::<Option<bool>>(LIST_APPEND)? != Some(true) {
+        return Ok(false);
+    }
+    let length = list.length().into_printed_external()?;
+    Ok(index.get_number().into_printed_external()? == length as f32 + 1.0)
+}
+
+/// Lists passed into `Add` or `Insert` have their contents added instead of the list itself,
+/// so they are wrapped in another list to be added as a single element.
+pub fn as_list_element(value: &ByondValue) -> ByondResult<ByondValue> {
+    if value.is_list() {
+        [value].to_byond()
+    } else {
+        Ok(value.clone())
+    }
+}
+
 /// Checks if `object` can be indexed into with the given `index`.
 ///
 /// Accounts for types that can only be indexed by certain kinds of values
@@ -1,6 +1,6 @@
 use std::ffi::CString;
 
-use self::indexing::validate_index;
+use self::indexing::{appends_on_write, check_list_bounds, validate_index};
 
 use super::Value;
 
@@ -12,13 +12,20 @@ use crate::{
     traits::AsPrintedExternal,
     types::PROC_DEFINABLE_TYPES,
     wrappers::{
-        wrapped_read_list_index, wrapped_read_var, wrapped_write_list_index, wrapped_write_var,
+        wrapped_append_list_element, wrapped_read_list_index, wrapped_read_var,
+        wrapped_write_list_index, wrapped_write_var,
     },
 };
 
 use mlua::{Function, IntoLua, IntoLuaMulti, MetaMethod, MultiValue, UserData, UserDataMethods};
 
 mod indexing;
+mod operators;
+mod typepath;
+pub use indexing::{as_list_element, read_list_index, resolve_list_index, LIST_APPEND};
+pub use operators::list_operator;
+use typepath::typepath_method;
+pub use typepath::{is_typepath, text_to_typepath, types_of};
 
 use crate::traits::AsPrintedExternalResult;
 
@@ -63,14 +70,23 @@ impl UserData for ByondObject {
                 Err(ByondError::NotAList.into_printed_external())
             }
         });
+        methods.add_meta_function(MetaMethod::Add, |_, (Value(left), Value(right))| {
+            list_operator("+", &left, &right)
+        });
+        methods.add_meta_function(MetaMethod::Sub, |_, (Value(left), Value(right))| {
+            list_operator("-", &left, &right)
+        });
         methods.add_meta_function(MetaMethod::ToString, |_, Value(this)| {
             CString::from_byond(this).into_printed_external()
         });
         methods.add_meta_function(
             MetaMethod::NewIndex,
-            |_, (Value(ref mut this), Value(ref index), Value(ref value))| {
+            |lua, (Value(ref mut this), Value(index), Value(ref value))| {
+                let index = &resolve_list_index(this, index)?;
                 validate_index(this, index).and_then(|()| {
-                    if this.is_list() {
+                    if this.is_list() && appends_on_write(lua, this, index)? {
+                        wrapped_append_list_element(this, value).into_printed_external()
+                    } else if this.is_list() {
                         wrapped_write_list_index(this, index, value).into_printed_external()
                     } else {
                         wrapped_write_var(this, index.get_string().unwrap(), value)
@@ -79,31 +95,32 @@ impl UserData for ByondObject {
                 })
             },
         );
-        methods.add_meta_function(
-            MetaMethod::Index,
-            |lua, (Value(ref this), Value(ref index))| {
-                validate_index(this, index)
-                    .and_then(|()| {
-                        if this.is_list() {
-                            wrapped_read_list_index(this, index)
-                                .into_printed_external()
-                                .and_then(|v| v.into_lua(lua))
-                        } else if (PROC_DEFINABLE_TYPES.contains(&this.get_type().0)
-                            || *this == WORLD)
-                            && call_global::<_, _, _, bool>("_hascall", [this, index])
-                                .into_printed_external()?
-                        {
-                            object_proccall_function(lua, index.get_string().unwrap())
-                                .and_then(|v| v.into_lua(lua))
-                        } else {
-                            wrapped_read_var(this, index.get_string().unwrap())
-                                .into_printed_external()
-                                .and_then(|v| v.into_lua(lua))
-                        }
-                    })
-                    .and_then(|b| b.into_lua(lua))
-            },
-        )
+        methods.add_meta_function(MetaMethod::Index, |lua, (Value(ref this), Value(index))| {
+            if is_typepath(this) {
+                return typepath_method(lua, &index);
+            }
+            let index = &resolve_list_index(this, index)?;
+            validate_index(this, index)
+                .and_then(|()| {
+                    if this.is_list() {
+                        check_list_bounds(this, index)?;
+                        wrapped_read_list_index(this, index)
+                            .into_printed_external()
+                            .and_then(|v| v.into_lua(lua))
+                    } else if (PROC_DEFINABLE_TYPES.contains(&this.get_type().0) || *this == WORLD)
+                        && call_global::<_, _, _, bool>("_hascall", [this, index])
+                            .into_printed_external()?
+                    {
+                        object_proccall_function(lua, index.get_string().unwrap())
+                            .and_then(|v| v.into_lua(lua))
+                    } else {
+                        wrapped_read_var(this, index.get_string().unwrap())
+                            .into_printed_external()
+                            .and_then(|v| v.into_lua(lua))
+                    }
+                })
+                .and_then(|b| b.into_lua(lua))
+        })
     }
 }
 
@@ -0,0 +1,18 @@
+use meowtonin::{call_global, ByondError, ByondValue};
+use mlua::prelude::LuaResult;
+
+use crate::{traits::AsPrintedExternalResult, value::Value};
+
+/// Creates a new list by applying one of DM's list operators (`+`, `-`, `|`, `&`, or `^`) to `left` and `right`.
+///
+/// Byondapi has no way to apply operators directly, so this calls a helper proc defined in `api.dm`.
+pub fn list_operator(operator: &str, left: &ByondValue, right: &ByondValue) -> LuaResult<Value> {
+    if !left.is_list() {
+        return Err(ByondError::NotAList).into_printed_external();
+    }
+    call_global::<_, _, _, Value>(
+        "_list_operator",
+        [&ByondValue::new_string(operator), left, right],
+    )
+    .into_printed_external()
+}
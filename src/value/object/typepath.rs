@@ -0,0 +1,42 @@
+use meowtonin::{call_global, ByondResult, ByondValue};
+use mlua::{
+    prelude::{LuaError, LuaResult, LuaValue},
+    Function, IntoLua, Lua,
+};
+
+use crate::{traits::AsPrintedExternalResult, types::TYPEPATH_TYPES, value::Value};
+
+#[inline]
+pub fn is_typepath(value: &ByondValue) -> bool {
+    TYPEPATH_TYPES.contains(&value.get_type().0)
+}
+
+/// Logically equivalent to `text2path(text)`, returning null if `text` is not a valid typepath.
+pub fn text_to_typepath(text: &str) -> ByondResult<ByondValue> {
+    call_global("_text2path", [ByondValue::new_string(text)])
+}
+
+/// Logically equivalent to `typesof(path)`. `path` can be a typepath or a string.
+pub fn types_of(path: &ByondValue) -> ByondResult<ByondValue> {
+    call_global("_typesof", [path])
+}
+
+fn parent(_: &Lua, Value(this): Value) -> LuaResult<Value> {
+    call_global("_typepath_parent", [this]).into_printed_external()
+}
+
+fn is_subtype_of(_: &Lua, (Value(this), Value(other)): (Value, Value)) -> LuaResult<bool> {
+    call_global("_ispath", [this, other]).into_printed_external()
+}
+
+/// Typepaths have no vars or procs to index, so they are indexed for the methods defined here instead.
+pub fn typepath_method<'lua>(lua: &'lua Lua, index: &ByondValue) -> LuaResult<LuaValue<'lua>> {
+    let name = index.get_string().into_printed_external()?;
+    match name.as_str() {
+        "parent" => Function::wrap(parent).into_lua(lua),
+        "is_subtype_of" => Function::wrap(is_subtype_of).into_lua(lua),
+        _ => Err(LuaError::external(format!(
+            "typepaths have no method named \"{name}\""
+        ))),
+    }
+}
@@ -0,0 +1,141 @@
+use meowtonin::{ByondError, ByondResult, ByondValue};
+use mlua::{
+    prelude::{LuaResult, LuaValue},
+    Function, IntoLua, IntoLuaMulti, Lua, MetaMethod, MultiValue, UserData, UserDataMethods,
+};
+
+use crate::{traits::AsPrintedExternalResult, wrappers::wrapped_read_list_range};
+
+use super::{object::read_list_index, ByondObject, Value};
+
+/// How many entries a view reads at once while it is being iterated over, unless otherwise specified.
+pub const DEFAULT_CHUNK_SIZE: usize = 256;
+
+/// A read-only proxy for a list that reads its entries on demand, instead of copying the whole list into a table.
+pub struct ListView {
+    list: ByondObject,
+    chunk_size: usize,
+}
+
+impl ListView {
+    pub fn new(list: ByondValue, chunk_size: Option<usize>) -> ByondResult<Self> {
+        if !list.is_list() {
+            return Err(ByondError::NotAList);
+        }
+        Ok(Self {
+            list: ByondObject::new(list),
+            chunk_size: chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE).max(1),
+        })
+    }
+
+    /// Creates an iterator function that returns the key and assoc value of each entry of the list, reading the list in chunks.
+    ///
+    /// Entries without an assoc value return `nil` as their value, and null elements are skipped, as they would end the iteration.
+    pub fn pairs<'lua>(&self, lua: &'lua Lua) -> LuaResult<Function<'lua>> {
+        let mut reader = self.reader(true);
+        lua.create_function_mut(move |lua, ()| loop {
+            match reader.next().into_printed_external()? {
+                Some((_, key, _)) if key.is_null() => continue,
+                Some((_, key, value)) => return (Value(key), Value(value)).into_lua_multi(lua),
+                None => return Ok(MultiValue::new()),
+            }
+        })
+    }
+
+    fn reader(&self, assoc: bool) -> ChunkReader {
+        ChunkReader {
+            list: ByondObject::new(self.list.0.clone()),
+            chunk_size: self.chunk_size,
+            assoc,
+            chunk: None,
+            entries: vec![],
+            chunk_start: 1,
+            next: 1,
+        }
+    }
+}
+
+/// Reads the entries of a list in order, a chunk at a time.
+struct ChunkReader {
+    list: ByondObject,
+    chunk_size: usize,
+    assoc: bool,
+    /// The copy of the list that the current chunk was read from, which keeps the values in the chunk alive between reads.
+    chunk: Option<ByondObject>,
+    entries: Vec<(ByondValue, ByondValue)>,
+    chunk_start: usize,
+    next: usize,
+}
+
+impl ChunkReader {
+    fn next(&mut self) -> ByondResult<Option<(usize, ByondValue, ByondValue)>> {
+        if self.next >= self.chunk_start + self.entries.len() {
+            // The length is checked before every chunk, so entries added or removed during iteration are accounted for.
+            let length = self.list.0.length()?;
+            if self.next > length {
+                self.chunk = None;
+                return Ok(None);
+            }
+            let end = length.min(self.next + self.chunk_size - 1) + 1;
+            let (copy, entries) =
+                wrapped_read_list_range(&self.list.0, self.next, end, self.assoc)?;
+            self.chunk = Some(ByondObject::new(copy));
+            self.entries = entries;
+            self.chunk_start = self.next;
+        }
+        let Some((key, value)) = self.entries.get(self.next - self.chunk_start).cloned() else {
+            return Ok(None);
+        };
+        self.next += 1;
+        Ok(Some((self.next - 1, key, value)))
+    }
+}
+
+/// Creates an iterator function that returns each index of the list along with the element at that index.
+fn ipairs_iterator(lua: &Lua, mut reader: ChunkReader) -> LuaResult<Function<'_>> {
+    lua.create_function_mut(
+        move |lua, ()| match reader.next().into_printed_external()? {
+            Some((index, key, _)) => (index, Value(key)).into_lua_multi(lua),
+            None => Ok(MultiValue::new()),
+        },
+    )
+}
+
+impl UserData for ListView {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| {
+            this.list.0.length().into_printed_external()
+        });
+        methods.add_meta_method(MetaMethod::Index, |_, this, Value(index)| {
+            read_list_index(&this.list.0, index)
+        });
+        methods.add_meta_method(MetaMethod::Iter, |lua, this, ()| {
+            ipairs_iterator(lua, this.reader(false))
+        });
+        methods.add_method("get", |_, this, Value(index)| {
+            read_list_index(&this.list.0, index)
+        });
+        methods.add_method("ipairs", |lua, this, ()| {
+            ipairs_iterator(lua, this.reader(false))
+        });
+        methods.add_method("pairs", |lua, this, ()| this.pairs(lua));
+        methods.add_method(
+            "slice",
+            |lua, this, (start, end): (Option<usize>, Option<usize>)| {
+                let length = this.list.0.length().into_printed_external()?;
+                let start = start.unwrap_or(1).max(1);
+                let end = end.unwrap_or(length).min(length);
+                let table = lua.create_table()?;
+                if start <= end {
+                    let (_copy, entries) =
+                        wrapped_read_list_range(&this.list.0, start, end + 1, false)
+                            .into_printed_external()?;
+                    entries
+                        .into_iter()
+                        .try_for_each(|(key, _)| table.raw_push(Value(key).into_lua(lua)?))?;
+                }
+                Ok(LuaValue::Table(table))
+            },
+        );
+    }
+}
@@ -4,7 +4,7 @@ use dreamluau_proc_macro::map_statics;
 use meowtonin::{byond_fn, call_global, ByondError, ByondResult, ByondValue, ToByond};
 
 use crate::{
-    types::{type_name_for_obj, VARS_TYPES},
+    types::{type_name_for_obj, TYPEPATH_TYPES, VARS_TYPES},
     value::Value,
 };
 
@@ -59,3 +59,103 @@ pub fn wrapped_read_list_index<K: ToByond>(target: &ByondValue, index: K) -> Byo
         target.read_list_index(&index)
     }
 }
+
+/// Reads the name and value of every var on `target`.
+///
+/// If the var get wrapper is set, each value is read through the wrapper, instead of reading the whole vars list at once.
+pub fn wrapped_read_vars(target: &ByondValue) -> ByondResult<Vec<(ByondValue, Value)>> {
+    let vars = target.read_var::<_, ByondValue>("vars")?;
+    if get_get_var_wrapper().is_some() {
+        vars.read_list()?
+            .into_iter()
+            .map(|name| {
+                let value = wrapped_read_var(target, name.get_string()?)?;
+                Ok((name, value))
+            })
+            .collect()
+    } else {
+        Ok(vars
+            .read_assoc_list()?
+            .into_iter()
+            .map(|[name, value]| (name, Value(value)))
+            .collect())
+    }
+}
+
+/// Copies the entries of the list from `start` up to but not including `end` in a single call,
+/// returning the copy along with its entries. Assoc values are only read if `assoc` is true.
+///
+/// The copy is returned so that the caller can keep the values in it alive for as long as they are needed.
+pub fn wrapped_read_list_range(
+    target: &ByondValue,
+    start: usize,
+    end: usize,
+    assoc: bool,
+) -> ByondResult<(ByondValue, Vec<(ByondValue, ByondValue)>)> {
+    if assoc && VARS_TYPES.contains(&target.get_type().0) && get_get_var_wrapper().is_some() {
+        return Err(ByondError::boxed(WrapperError::Forbidden {
+            action: format!(
+                "direct reading of {} assoc values",
+                type_name_for_obj(target)
+            ),
+            wrapper: "var get".into(),
+        }));
+    }
+    let copy = target.call::<_, _, _, ByondValue>("Copy", [start, end])?;
+    let entries = if assoc {
+        copy.read_assoc_list()?
+            .into_iter()
+            .map(|[key, value]| (key, value))
+            .collect()
+    } else {
+        copy.read_list()?
+            .into_iter()
+            .map(|key| (key, ByondValue::NULL))
+            .collect()
+    };
+    Ok((copy, entries))
+}
+
+/// Returns the object that the initial values of `target`'s vars are read from.
+///
+/// The vars of a typepath cannot be read directly, so they are read from a prototype of the type,
+/// which is created the first time it is needed and kept for later reads.
+fn initial_source(target: &ByondValue) -> ByondResult<ByondValue> {
+    if TYPEPATH_TYPES.contains(&target.get_type().0) {
+        call_global("_prototype", [target])
+    } else {
+        Ok(target.clone())
+    }
+}
+
+/// The var get wrapper can only read the current values of an object's vars,
+/// so it is called on the var and its return value is discarded, which lets it forbid reading the var.
+fn check_initial_read(source: &ByondValue, var: &ByondValue) -> ByondResult<()> {
+    match get_get_var_wrapper() {
+        Some(wrapper) => call_global::<_, _, _, ByondValue>(wrapper, [source, var]).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Reads the initial value of `var` on `target`, which can be either an object or a typepath.
+pub fn wrapped_read_initial(target: &ByondValue, var: String) -> ByondResult<Value> {
+    let source = initial_source(target)?;
+    let var_as_value = var.to_byond()?;
+    check_initial_read(&source, &var_as_value)?;
+    call_global("_initial", [&source, &var_as_value])
+}
+
+/// Reads the name and initial value of every var on `target`, which can be either an object or a typepath.
+///
+/// The list the entries were read from is returned along with them, so that the caller can keep the values alive.
+pub fn wrapped_read_initial_vars(
+    target: &ByondValue,
+) -> ByondResult<(ByondValue, Vec<[ByondValue; 2]>)> {
+    let source = initial_source(target)?;
+    let vars = call_global::<_, _, _, ByondValue>("_initial_vars", [&source])?;
+    let entries = vars.read_assoc_list()?;
+    entries
+        .iter()
+        .try_for_each(|[name, _]| check_initial_read(&source, name))?;
+    Ok((vars, entries))
+}
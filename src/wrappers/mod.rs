@@ -6,9 +6,14 @@ mod object_call;
 mod print;
 mod set_var;
 
-pub use get_var::{set_var_get_wrapper, wrapped_read_list_index, wrapped_read_var};
+pub use get_var::{
+    set_var_get_wrapper, wrapped_read_initial, wrapped_read_initial_vars, wrapped_read_list_index,
+    wrapped_read_list_range, wrapped_read_var, wrapped_read_vars,
+};
 pub use global_call::{set_global_call_wrapper, wrapped_global_call};
 pub use new::{set_new_wrapper, wrapped_new};
 pub use object_call::{set_object_call_wrapper, wrapped_object_call};
 pub use print::{print, set_print_wrapper};
-pub use set_var::{set_var_set_wrapper, wrapped_write_list_index, wrapped_write_var};
+pub use set_var::{
+    set_var_set_wrapper, wrapped_append_list_element, wrapped_write_list_index, wrapped_write_var,
+};
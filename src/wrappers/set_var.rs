@@ -3,7 +3,10 @@ use std::cell::RefCell;
 use dreamluau_proc_macro::map_statics;
 use meowtonin::{byond_fn, call_global, ByondError, ByondResult, ByondValue, ToByond};
 
-use crate::types::{type_name_for_obj, VARS_TYPES};
+use crate::{
+    types::{type_name_for_obj, VARS_TYPES},
+    value::as_list_element,
+};
 
 use super::error::WrapperError;
 
@@ -44,17 +47,30 @@ pub fn wrapped_write_var(
     }
 }
 
-pub fn wrapped_write_list_index<K: ToByond, V: ToByond>(
-    target: &mut ByondValue,
-    index: K,
-    value: V,
-) -> ByondResult<()> {
+fn check_list_write(target: &ByondValue) -> ByondResult<()> {
     if VARS_TYPES.contains(&target.get_type().0) && get_set_var_wrapper().is_some() {
         Err(ByondError::boxed(WrapperError::Forbidden {
             action: format!("direct modification of {} lists", type_name_for_obj(target)),
             wrapper: "var set".into(),
         }))
     } else {
-        target.write_list_index(index, value)
+        Ok(())
     }
 }
+
+pub fn wrapped_write_list_index<K: ToByond, V: ToByond>(
+    target: &mut ByondValue,
+    index: K,
+    value: V,
+) -> ByondResult<()> {
+    check_list_write(target).and_then(|()| target.write_list_index(index, value))
+}
+
+/// Appends `value` to the end of `target` as a single element,
+/// subject to the same restrictions as writing an index of `target`.
+pub fn wrapped_append_list_element(target: &mut ByondValue, value: &ByondValue) -> ByondResult<()> {
+    check_list_write(target)
+        .and_then(|()| as_list_element(value))
+        .and_then(|element| target.call::<_, _, _, ByondValue>("Add", [element]))
+        .map(|_| ())
+}
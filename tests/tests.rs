@@ -8,7 +8,7 @@ use std::{
     ffi::CStr,
     fmt::Display,
     fs,
-    io::{Error, ErrorKind, Read, Result as IoResult, Write},
+    io::{Error, Read, Result as IoResult, Write},
     net::{Ipv4Addr, TcpStream},
     ops::DerefMut,
     path::{PathBuf, MAIN_SEPARATOR_STR},
@@ -91,7 +91,7 @@ fn send_topic<S: DerefMut<Target = TcpStream>>(
 ) -> IoResult<TopicResponse> {
     let len = topic.len() + 6;
     if len > u16::MAX as usize {
-        return Err(Error::new(ErrorKind::Other, "payload size too large"));
+        return Err(Error::other("payload size too large"));
     }
     stream.write_all(
         [0x00, 0x83]
@@ -150,10 +150,8 @@ fn dreamdaemon() -> RwLockWriteGuard<'static, Child> {
                 dreamdaemon.kill();
             }
             #[cfg(debug_assertions)]
-            for logfile in glob("tests/dm/meowtonin*.log").unwrap() {
-                if let Ok(file) = logfile {
-                    fs::remove_file(file);
-                }
+            for file in glob("tests/dm/meowtonin*.log").unwrap().flatten() {
+                fs::remove_file(file);
             }
             fs::remove_file("tests/dm/tests.dmb");
             fs::remove_file("tests/dm/tests.int");
@@ -203,6 +201,12 @@ macro_rules! simple_test {
 simple_test!(hello_world);
 
 simple_test!(usr_pushing);
+simple_test!(usr_kept_after_compile_error);
+
+simple_test!(usr_preservation);
+
+simple_test!(invocation_context);
+simple_test!(run_options);
 
 simple_test!(calling);
 
@@ -210,6 +214,59 @@ simple_test!(sleeping);
 
 simple_test!(yielding);
 
+simple_test!(yield_error);
+
+simple_test!(thread_groups);
+
+simple_test!(thread_groups_with_same_name);
+
+simple_test!(yield_timeout);
+simple_test!(nested_calls_keep_expired_threads);
+
+simple_test!(nested_sleeping);
+
+simple_test!(sleep_ids);
+simple_test!(kill_during_awaken_all);
+
+simple_test!(completion_callbacks);
+simple_test!(failing_completion_callbacks);
+simple_test!(wrapped_completion_callbacks);
+
+simple_test!(call_depth_limit);
+simple_test!(call_depth_keeps_suspended_threads);
+
+simple_test!(pairs_conversion);
+
+simple_test!(ordered_tables);
+
+simple_test!(conversion_hooks);
+
+simple_test!(conversion_limits);
+
+simple_test!(json);
+
+simple_test!(vector_coordinates);
+
+simple_test!(buffers);
+
+simple_test!(list_views);
+
+simple_test!(assoc_pairs);
+
+simple_test!(table_functions_on_lists);
+
+simple_test!(list_sort);
+
+simple_test!(functional_list_helpers);
+
+simple_test!(list_filter);
+
+simple_test!(list_constructors_and_operators);
+
+simple_test!(typepaths);
+
+simple_test!(initial_values);
+
 simple_test!(reading);
 
 simple_test!(writing);